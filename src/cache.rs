@@ -0,0 +1,170 @@
+//! A tiny on-disk cache for things that are slow to fetch but rarely change,
+//! like bots' pack lists and API responses.
+
+use std::{fs, path::PathBuf, time::Duration};
+
+/// Default size limit of each cache namespace
+pub const DEFAULT_MAX_SIZE: u64 = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl Cache {
+    /// Opens (and creates, if needed) the cache directory for `namespace`.
+    /// Returns `None` if there's no cache directory on this system.
+    pub fn open(namespace: &str) -> Option<Self> {
+        Self::open_in(root()?.join(namespace))
+    }
+
+    /// Opens (and creates, if needed) a cache in `dir`
+    pub fn open_in(dir: impl Into<PathBuf>) -> Option<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).ok()?;
+        Some(Self {
+            dir,
+            max_size: DEFAULT_MAX_SIZE,
+        })
+    }
+
+    /// Limits how many bytes the cache can take. The oldest values are
+    /// evicted to make room for new ones.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Returns the value stored for `key`, if it's younger than `ttl`
+    pub fn get(&self, key: &str, ttl: Duration) -> Option<String> {
+        let path = self.path(key);
+        let age = fs::metadata(&path).ok()?.modified().ok()?.elapsed().ok()?;
+        if age > ttl {
+            return None;
+        }
+        fs::read_to_string(path).ok()
+    }
+
+    /// Stores `value` for `key`. Failing to write to the cache is not an error,
+    /// the value will just be fetched again next time.
+    pub fn put(&self, key: &str, value: &str) {
+        if fs::write(self.path(key), value).is_ok() {
+            self.trim();
+        }
+    }
+
+    /// Removes every value in every namespace
+    pub fn clear_all() {
+        let namespaces = root().and_then(|root| fs::read_dir(root).ok());
+        for entry in namespaces.into_iter().flatten().flatten() {
+            let cache = Self {
+                dir: entry.path(),
+                max_size: DEFAULT_MAX_SIZE,
+            };
+            cache.clear();
+        }
+    }
+
+    /// Removes every value in the cache
+    pub fn clear(&self) {
+        for entry in fs::read_dir(&self.dir).into_iter().flatten().flatten() {
+            fs::remove_file(entry.path()).ok();
+        }
+    }
+
+    /// Evicts the oldest values until the cache fits in `max_size`
+    fn trim(&self) {
+        let mut files: Vec<_> = match fs::read_dir(&self.dir) {
+            Ok(dir) => dir
+                .flatten()
+                .filter_map(|entry| {
+                    let metadata = entry.metadata().ok()?;
+                    Some((entry.path(), metadata.len(), metadata.modified().ok()?))
+                })
+                .collect(),
+            Err(_) => return,
+        };
+
+        let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+        files.sort_by_key(|(_, _, modified)| *modified);
+        for (path, len, _) in files {
+            if size <= self.max_size {
+                break;
+            }
+            if fs::remove_file(path).is_ok() {
+                size -= len;
+            }
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}", fnv1a(key)))
+    }
+}
+
+fn root() -> Option<PathBuf> {
+    Some(dirs::cache_dir()?.join("mahou"))
+}
+
+/// A stable hash, so cache entries survive across builds
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, thread, time::SystemTime};
+
+    fn cache(name: &str) -> Cache {
+        let dir = std::env::temp_dir().join(format!("mahou-cache-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        Cache::open_in(dir).unwrap()
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        let cache = cache("hits");
+        let ttl = Duration::from_secs(60);
+        assert_eq!(cache.get("bots", ttl), None);
+        cache.put("bots", "[1, 2]");
+        assert_eq!(cache.get("bots", ttl).as_deref(), Some("[1, 2]"));
+        assert_eq!(cache.get("Bots", ttl), None);
+
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(cache.get("bots", Duration::from_millis(10)), None);
+
+        cache.clear();
+        assert_eq!(cache.get("bots", ttl), None);
+        fs::remove_dir_all(&cache.dir).ok();
+    }
+
+    #[test]
+    fn evicts_the_oldest_first() {
+        let cache = cache("trim").with_max_size(25);
+        let now = SystemTime::now();
+        for (i, key) in ["a", "b", "c"].into_iter().enumerate() {
+            cache.put(key, "0123456789");
+            let age = Duration::from_secs(60 * (3 - i as u64));
+            File::options()
+                .write(true)
+                .open(cache.path(key))
+                .unwrap()
+                .set_modified(now - age)
+                .unwrap();
+        }
+        // "a" was evicted to fit "c" already
+        let ttl = Duration::from_secs(60 * 60);
+        assert_eq!(cache.get("a", ttl), None);
+        assert!(cache.get("b", ttl).is_some());
+
+        cache.put("d", "0123456789");
+        assert_eq!(cache.get("b", ttl), None);
+        assert!(cache.get("c", ttl).is_some());
+        assert!(cache.get("d", ttl).is_some());
+        fs::remove_dir_all(&cache.dir).ok();
+    }
+}
@@ -0,0 +1,121 @@
+//! Pieces of `mahou daemon`, which syncs subscriptions every so often.
+
+use fs2::FileExt;
+use rand::prelude::*;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    time::Duration,
+};
+
+/// Held while syncing subscriptions, so that two mahous don't download the
+/// same episodes at once. It's released when dropped, or when mahou exits in
+/// any way.
+pub struct Lock {
+    file: File,
+}
+
+impl Lock {
+    /// The lock file, which has the PID of whoever holds the lock
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::data_dir()?.join("mahou").join("sync.pid"))
+    }
+
+    pub fn acquire() -> Result<Self, String> {
+        let path = Self::path().ok_or("There's no data directory to keep the lock file in")?;
+        let error = |e: std::io::Error| format!("Couldn't lock {}: {}", path.display(), e);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(error)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(error)?;
+
+        if file.try_lock_exclusive().is_err() {
+            // Some systems don't let us read a locked file
+            let pid = fs::read_to_string(&path).unwrap_or_default();
+            return Err(match pid.trim() {
+                "" => "Another mahou is already syncing subscriptions".to_owned(),
+                pid => format!(
+                    "Another mahou (PID {}) is already syncing subscriptions",
+                    pid
+                ),
+            });
+        }
+        file.set_len(0).map_err(error)?;
+        write!(file, "{}", std::process::id()).map_err(error)?;
+        Ok(Self { file })
+    }
+
+    /// Empties the lock file, for when mahou has to exit without dropping the
+    /// lock it holds. The OS releases the lock itself.
+    pub fn clear() {
+        if let Some(path) = Self::path() {
+            OpenOptions::new()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_len(0))
+                .ok();
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        self.file.set_len(0).ok();
+    }
+}
+
+/// The longest `--interval` there can be between syncs, in minutes: a week
+pub const MAX_INTERVAL: u64 = 7 * 24 * 60;
+
+/// Parses `--interval`, which is in minutes
+pub fn parse_interval(s: &str) -> Result<u64, String> {
+    match s.parse::<u64>() {
+        Ok(0) => Err("The interval must be at least 1 minute".to_owned()),
+        Ok(minutes) if minutes > MAX_INTERVAL => Err(format!(
+            "The interval can be at most {} minutes",
+            MAX_INTERVAL
+        )),
+        Ok(minutes) => Ok(minutes),
+        Err(_) => Err(format!("Invalid interval {} (expected minutes)", s)),
+    }
+}
+
+/// `interval`, give or take a fifth, so that checks don't happen at the same
+/// moment every time
+pub fn jittered(interval: Duration) -> Duration {
+    interval.mul_f64(thread_rng().gen_range(0.8..1.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals() {
+        assert_eq!(parse_interval("30"), Ok(30));
+        assert_eq!(parse_interval("10080"), Ok(MAX_INTERVAL));
+        assert!(parse_interval("0").is_err());
+        assert!(parse_interval("10081").is_err());
+        assert!(parse_interval("307445734561825861").is_err());
+        assert!(parse_interval("soon").is_err());
+    }
+
+    #[test]
+    fn jitter_stays_within_a_fifth() {
+        let interval = Duration::from_secs(30 * 60);
+        for _ in 0..1000 {
+            let wait = jittered(interval);
+            assert!(wait >= interval.mul_f64(0.8), "{:?} is too short", wait);
+            assert!(wait < interval.mul_f64(1.2), "{:?} is too long", wait);
+        }
+        let longest = Duration::from_secs(MAX_INTERVAL * 60);
+        assert!(jittered(longest) < longest.mul_f64(1.2));
+    }
+}
@@ -1,26 +1,169 @@
+use super::{Error, Result};
+use crate::library::Destination;
 use lazy_static::lazy_static;
+use rand::prelude::*;
 use regex::Regex;
-use std::{net::IpAddr, path::Path};
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, Shutdown, TcpStream},
+    time::Duration,
+};
 
 lazy_static! {
     pub static ref DCC_SEND_REGEX: Regex =
-        Regex::new(r#"DCC SEND "?(.*)"? (?:(\d+)|((?:[0-9a-fA-F]*:){2,}[0-9a-fA-F]*)) (\d+) (\d+)"#).unwrap();
-    pub static ref PING_REGEX: Regex = Regex::new(r#"PING :\d+"#).unwrap();
-    pub static ref JOIN_REGEX: Regex = Regex::new(r#"JOIN :#.*"#).unwrap();
+        Regex::new(r#"DCC SEND "?([^"]*?)"? (?:(\d+)|((?:[0-9a-fA-F]*:){2,}[0-9a-fA-F]*)) (\d+) (\d+)"#).unwrap();
+    /// Servers ping with numbers or with their own name
+    pub static ref PING_REGEX: Regex = Regex::new(r#"^(?::\S+ )?PING :?\S+"#).unwrap();
+    pub static ref JOIN_REGEX: Regex = Regex::new(r#"JOIN :?(#\S+)"#).unwrap();
+    pub static ref SENDER_REGEX: Regex = Regex::new(r#"^:([^!\s]+)!"#).unwrap();
+    /// Captures the sender, command, target and text of PRIVMSGs and NOTICEs
+    pub static ref MESSAGE_REGEX: Regex =
+        Regex::new(r#"^:([^!\s]+)!\S* (PRIVMSG|NOTICE) (\S+) :(.*?)\r?\n?$"#).unwrap();
+    static ref BOT_ADDRESS_REGEX: Regex =
+        Regex::new(r#"^([^@\s]+)@([^/\s]+)/(#?[^\s]+)$"#).unwrap();
 }
 
-#[derive(Debug, Clone)]
+/// Channel names as they should be sent to the server, with a leading `#`
+pub fn channel_name(channel: &str) -> String {
+    format!("#{}", channel.trim_start_matches('#'))
+}
+
+/// The nickname of whoever sent a message
+pub fn sender(message: &str) -> Option<&str> {
+    SENDER_REGEX
+        .captures(message)
+        .and_then(|captures| captures.get(1))
+        .map(|m| m.as_str())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Config {
     pub server: String,
     pub channel: String,
     pub nickname: String,
 }
 
+/// Where to find a bot, written as `bot@server:port/#channel`
+#[derive(Debug, Clone)]
+pub struct BotAddress {
+    pub bot: String,
+    pub config: Config,
+}
+
+impl std::str::FromStr for BotAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let captures = BOT_ADDRESS_REGEX.captures(s).ok_or_else(|| {
+            format!(
+                "Invalid bot address {} (expected bot@server:port/#channel)",
+                s
+            )
+        })?;
+        let server = match captures[2].contains(':') {
+            true => captures[2].to_owned(),
+            false => format!("{}:6667", &captures[2]),
+        };
+        Ok(Self {
+            bot: captures[1].to_owned(),
+            config: Config {
+                server,
+                channel: channel_name(&captures[3]),
+                nickname: random_nickname(),
+            },
+        })
+    }
+}
+
+/// A nickname that hopefully isn't taken yet
+pub fn random_nickname() -> String {
+    format!("real-person-{:x}", thread_rng().gen::<u32>())
+}
+
+/// A registered connection to an IRC server
+pub struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    /// Connects to the server and registers with the config's nickname. Reads
+    /// give up after `read_timeout`, so callers can do other work meanwhile.
+    pub fn open(config: &Config, read_timeout: Duration) -> Result<Self> {
+        let stream = TcpStream::connect(&config.server).map_err(Error::Connection)?;
+        stream
+            .set_read_timeout(Some(read_timeout))
+            .map_err(Error::Connection)?;
+
+        let mut connection = Self {
+            stream,
+            buffer: Vec::new(),
+        };
+        connection.send(&format!("NICK {}", config.nickname))?;
+        connection.send(&format!("USER {} 0 * {}", config.nickname, config.nickname))?;
+        Ok(connection)
+    }
+
+    pub fn set_read_timeout(&mut self, read_timeout: Duration) -> Result<()> {
+        self.stream
+            .set_read_timeout(Some(read_timeout))
+            .map_err(Error::Connection)
+    }
+
+    /// Sends a single command. The line terminator is added automatically.
+    pub fn send(&mut self, command: &str) -> Result<()> {
+        self.stream.write_all(command.as_bytes())?;
+        self.stream.write_all(b"\r\n")?;
+        Ok(())
+    }
+
+    /// Reads the next line from the server, or `None` if nothing arrived within
+    /// the read timeout
+    pub fn next_message(&mut self) -> Result<Option<String>> {
+        let mut buffer = [0; 512];
+        while !self.buffer.contains(&b'\n') {
+            let count = match self.stream.read(&mut buffer[..]) {
+                Ok(0) => return Err(Error::Connection(io::ErrorKind::UnexpectedEof.into())),
+                Ok(count) => count,
+                Err(e) if is_timeout(&e) => return Ok(None),
+                Err(e) => return Err(Error::Connection(e)),
+            };
+            self.buffer.extend_from_slice(&buffer[..count]);
+        }
+        let endline_offset = self.buffer.iter().position(|&b| b == b'\n').unwrap() + 1;
+        let message = String::from_utf8_lossy(&self.buffer[..endline_offset]).into_owned();
+        self.buffer.drain(..endline_offset);
+        Ok(Some(message))
+    }
+
+    pub fn quit(mut self) -> Result<()> {
+        self.send("QUIT :my job is done here!")?;
+        self.stream.shutdown(Shutdown::Both).ok();
+        Ok(())
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Packages to download over a single IRC connection
 pub struct Request<'p> {
     pub config: Config,
+    pub batches: Vec<Batch>,
+    pub destination: &'p Destination,
+    /// Where to send files while they download, besides saving them
+    pub stream: Option<super::Stream>,
+}
+
+/// Packages requested from a single bot, which can be found in `channel`
+pub struct Batch {
+    pub channel: String,
     pub bot: String,
     pub packages: Vec<String>,
-    pub directory: &'p Path,
 }
 
 pub struct DCCSend {
@@ -29,3 +172,29 @@ pub struct DCCSend {
     pub port: String,
     pub file_size: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dcc_send_file_names() {
+        let cases = [
+            (
+                "\x01DCC SEND \"[SubsPlease] Show - 01 (1080p) [ABCD1234].mkv\" 3232235777 5000 1024\x01",
+                "[SubsPlease] Show - 01 (1080p) [ABCD1234].mkv",
+            ),
+            (
+                "\x01DCC SEND [SubsPlease]_Show_-_01_(1080p).mkv 3232235777 5000 1024\x01",
+                "[SubsPlease]_Show_-_01_(1080p).mkv",
+            ),
+            ("\x01DCC SEND \"Show.mkv\" ::1 5000 1024\x01", "Show.mkv"),
+        ];
+        for (message, filename) in cases {
+            let captures = DCC_SEND_REGEX.captures(message).unwrap();
+            assert_eq!(&captures[1], filename, "{}", message);
+            assert_eq!(&captures[4], "5000", "{}", message);
+            assert_eq!(&captures[5], "1024", "{}", message);
+        }
+    }
+}
@@ -1,14 +1,28 @@
 /// Mostly copied from https://github.com/DeGuitard/anime-cli/
 /// Error handling is kind of whack...
 pub mod irc;
+mod pool;
+mod scheduler;
+mod session;
+mod stream;
 
+pub(crate) use pool::Link;
+pub use pool::Pool;
+use scheduler::Scheduler;
+pub use scheduler::{parse_limit, BotLimit, Limits};
+pub use session::Session;
+use stream::Tee;
+pub use stream::{Stream, PLAY_BUFFER};
+
+use crate::library::Destination;
 use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressState, ProgressStyle};
-use std::fs::File;
+use std::collections::HashSet;
+use std::fs::{self, File};
 use std::io::{self, Read, Write};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, TcpStream};
-use std::path::Path;
-use std::str::from_utf8;
-use std::time::Duration;
+use std::path::PathBuf;
+use std::sync::{atomic::AtomicUsize, mpsc};
+use std::time::{Duration, Instant};
 use std::{fmt, thread};
 use thiserror::Error;
 
@@ -26,21 +40,58 @@ pub enum Error {
 
 type Result<T> = std::result::Result<T, Error>;
 
-pub fn download(
-    entry: &crate::finder::Entry,
-    config: irc::Config,
-    directory: impl AsRef<Path>,
+/// A file that was downloaded. Files skipped because they already existed
+/// aren't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completed {
+    /// As the bot sent it, which may not be how finders named it
+    pub filename: String,
+    /// The package number we asked the bot for
+    pub package: String,
+    pub path: PathBuf,
+    pub bot: String,
+    pub server: String,
+    pub channel: String,
+    pub size: u64,
+    /// CRC32 of the file, in the uppercase form release names use
+    pub crc: String,
+    pub duration: Duration,
+}
+
+/// Called as soon as each file is downloaded
+pub type OnComplete<'a> = &'a (dyn Fn(&Completed) + Sync);
+
+/// How often the IRC connection stops waiting for messages to check on
+/// finished transfers
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub fn download(entry: &crate::finder::Entry, destination: &Destination) -> Result<()> {
+    download_many(std::slice::from_ref(entry), destination, &Limits::default())
+}
+
+/// Downloads several entries, using a single IRC connection per network and
+/// sending one multi-package request to each bot. Packages are only requested
+/// once a transfer slot is free, according to `limits`.
+pub fn download_many(
+    entries: &[crate::finder::Entry],
+    destination: &Destination,
+    limits: &Limits,
 ) -> Result<()> {
-    connect_and_download(irc::Request {
-        config,
-        bot: entry.bot_name.clone(),
-        packages: vec![entry.package_number.to_string()],
-        directory: directory.as_ref(),
-    })
+    let mut session = Session::new(destination, limits);
+    for entry in entries {
+        session.add(entry);
+    }
+    session.run()
 }
 
-fn connect_and_download(request: irc::Request) -> Result<()> {
-    let multibar = MultiProgress::new();
+fn connect_and_download(
+    request: irc::Request,
+    limits: &Limits,
+    multibar: &MultiProgress,
+    active_total: &AtomicUsize,
+    on_complete: Option<OnComplete>,
+    pool: Option<&Pool>,
+) -> Result<()> {
     let new_progressbar = |total_bytes: u64| {
         let pb = ProgressBar::new(total_bytes);
 
@@ -73,52 +124,164 @@ fn connect_and_download(request: irc::Request) -> Result<()> {
         pb
     };
 
-    multibar
-        .println(format!("Connecting to {}...", request.config.server))
-        .unwrap();
-
     let mut download_handles = Vec::new();
+    let mut link = match pool.and_then(|pool| pool.take(&request.config.server)) {
+        Some(mut link) => {
+            multibar
+                .println(format!(
+                    "Reusing the connection to {}",
+                    request.config.server
+                ))
+                .unwrap();
+            link.connection.set_read_timeout(POLL_INTERVAL)?;
+            link
+        }
+        None => {
+            multibar
+                .println(format!("Connecting to {}...", request.config.server))
+                .unwrap();
+            let link = Link::open(&request.config, POLL_INTERVAL)?;
+            multibar.println("Connected! Pinging server...").unwrap();
+            link
+        }
+    };
+    let channels: HashSet<_> = request
+        .batches
+        .iter()
+        .map(|b| irc::channel_name(&b.channel))
+        .collect();
     let mut has_joined = false;
-    let mut stream = log_in(&request)?;
 
-    multibar.println("Connected! Pinging server...").unwrap();
+    let mut scheduler = Scheduler::new(limits, active_total, &request.batches);
+    let total_packages: usize = request.batches.iter().map(|b| b.packages.len()).sum();
+    let mut finished_packages = 0;
+    let (done_tx, done_rx) = mpsc::channel::<(String, Option<Completed>)>();
 
-    let mut message_buffer = String::new();
-    while download_handles.len() < request.packages.len() {
-        let message = read_next_message(&mut stream, &mut message_buffer)?;
+    while finished_packages < total_packages {
+        // Channels can only be joined once the server has pinged us
+        if link.registered && !has_joined {
+            for channel in &channels {
+                if !link.joined_channels.contains(&channel.to_lowercase()) {
+                    multibar.println(format!("Joining {}...", channel)).unwrap();
+                    link.connection.send(&format!("JOIN {}", channel))?;
+                }
+            }
+            has_joined = true;
+        }
 
-        if irc::PING_REGEX.is_match(&message) {
-            multibar.println(format!("< {}", message)).unwrap();
-            multibar.println("Joining channel...").unwrap();
-            let pong = message.replace("PING", "PONG");
-            stream.write_all(pong.as_bytes())?;
-            if !has_joined {
-                let channel_join_cmd = format!("JOIN #{}\r\n", request.config.channel);
-                stream.write_all(channel_join_cmd.as_bytes())?;
-                has_joined = true;
+        for (bot, completed) in done_rx.try_iter() {
+            scheduler.finish(&bot);
+            finished_packages += 1;
+            if let (Some(completed), Some(on_complete)) = (completed, on_complete) {
+                on_complete(&completed);
             }
         }
-        if irc::JOIN_REGEX.is_match(&message) {
+
+        for (bot, package) in scheduler.expire() {
+            multibar
+                .println(format!(
+                    "~ {} didn't start sending package #{}, giving up on it",
+                    bot, package
+                ))
+                .unwrap();
+            finished_packages += 1;
+        }
+
+        for (channel, bot, package) in scheduler.expire_unjoined(&link.joined_channels) {
+            multibar
+                .println(format!(
+                    "~ couldn't join {}, so package #{} from {} failed",
+                    channel, package, bot
+                ))
+                .unwrap();
+            finished_packages += 1;
+        }
+
+        while let Some((bot, package)) = scheduler.next(&link.joined_channels) {
+            multibar
+                .println(format!(
+                    "Starting download of package #{} from {}",
+                    package, bot
+                ))
+                .unwrap();
+            link.connection
+                .send(&format!("PRIVMSG {} :xdcc send #{}", bot, package))?;
+        }
+
+        let message = match link.connection.next_message()? {
+            Some(message) => message,
+            None => continue,
+        };
+
+        // PINGs and our own JOINs
+        if link.handle(&message)? {
             multibar.println(format!("< {}", message)).unwrap();
-            for package in &request.packages {
-                multibar
-                    .println(format!("Starting download of package #{}", package))
-                    .unwrap();
-                let xdcc_send_cmd = format!("PRIVMSG {} :xdcc send #{}\r\n", request.bot, package);
-                stream.write_all(xdcc_send_cmd.as_bytes())?;
-            }
         }
         if irc::DCC_SEND_REGEX.is_match(&message) {
             multibar.println(format!("< {}", message)).unwrap();
-            let directory = request.directory.to_owned();
-            let request = parse_dcc_send(&message);
-            let bar = multibar.add(new_progressbar(request.file_size as u64));
-            let handle = thread::spawn(move || download_file(request, bar, directory));
+            let bot = irc::sender(&message).unwrap_or_default().to_owned();
+            let send = match parse_dcc_send(&message) {
+                Some(send) => send,
+                None => {
+                    multibar
+                        .println(format!("~ ignoring an invalid file offer from {}", bot))
+                        .unwrap();
+                    continue;
+                }
+            };
+            let package = match scheduler.start(&bot) {
+                Some(package) => package,
+                None => {
+                    multibar
+                        .println(format!("~ ignoring a file {} sent too late", bot))
+                        .unwrap();
+                    continue;
+                }
+            };
+            let channel = request
+                .batches
+                .iter()
+                .find(|b| b.bot.eq_ignore_ascii_case(&bot))
+                .map(|b| b.channel.clone())
+                .unwrap_or_default();
+            let server = request.config.server.clone();
+            let destination = request.destination.clone();
+            let stream = request.stream.clone();
+            let bar = multibar.add(new_progressbar(send.file_size as u64));
+            let done_tx = done_tx.clone();
+            let handle = thread::spawn(move || -> Result<()> {
+                let started = Instant::now();
+                let (filename, size) = (send.filename.clone(), send.file_size as u64);
+                let result = download_file(send, &bar, destination, stream);
+                let duration = started.elapsed();
+                let completed = match &result {
+                    Ok(Some(saved)) => Some(Completed {
+                        filename,
+                        package,
+                        path: saved.path.clone(),
+                        bot: bot.clone(),
+                        server,
+                        channel,
+                        size,
+                        crc: saved.crc.clone(),
+                        duration,
+                    }),
+                    _ => None,
+                };
+                done_tx.send((bot, completed)).ok();
+                // The slot and the history don't wait for whoever's watching
+                if let Some(tee) = result?.and_then(|saved| saved.tee) {
+                    tee.wait(&bar);
+                }
+                Ok(())
+            });
             download_handles.push(handle);
         }
     }
-    stream.write_all("QUIT :my job is done here!\r\n".as_bytes())?;
-    stream.shutdown(Shutdown::Both).unwrap();
+    match pool {
+        Some(pool) => pool.put(request.config.server.clone(), link),
+        None => link.connection.quit()?,
+    }
     download_handles.into_iter().try_for_each(|handle| {
         handle
             .join()
@@ -128,75 +291,176 @@ fn connect_and_download(request: irc::Request) -> Result<()> {
     Ok(())
 }
 
-fn log_in(request: &irc::Request) -> Result<TcpStream> {
-    let mut stream = TcpStream::connect(&request.config.server).map_err(Error::Connection)?;
-    stream.write_all(format!("NICK {}\r\n", request.config.nickname).as_bytes())?;
-    stream.write_all(
-        format!(
-            "USER {} 0 * {}\r\n",
-            request.config.nickname, request.config.nickname
-        )
-        .as_bytes(),
-    )?;
-    Ok(stream)
-}
-
-fn read_next_message(stream: &mut TcpStream, message_builder: &mut String) -> Result<String> {
-    let mut buffer = [0; 4];
-    while !message_builder.contains('\n') {
-        let count = stream.read(&mut buffer[..])?;
-        message_builder.push_str(from_utf8(&buffer[..count]).unwrap_or_default());
-    }
-    let endline_offset = message_builder.find('\n').unwrap() + 1;
-    let message = message_builder.get(..endline_offset).unwrap().to_string();
-    message_builder.replace_range(..endline_offset, "");
-    Ok(message)
-}
-
-fn parse_dcc_send(message: &str) -> irc::DCCSend {
-    let captures = irc::DCC_SEND_REGEX.captures(message).unwrap();
+/// Parses a DCC SEND offer, or returns `None` if it's malformed
+pub(crate) fn parse_dcc_send(message: &str) -> Option<irc::DCCSend> {
+    let captures = irc::DCC_SEND_REGEX.captures(message)?;
     let ip = match (captures.get(2), captures.get(3)) {
-        (Some(v4), _) => {
-            let ip = v4.as_str().parse::<u32>().unwrap();
-            IpAddr::from(Ipv4Addr::from(ip))
-        }
-        (_, Some(v6)) => {
-            let ip = v6.as_str().parse::<Ipv6Addr>().unwrap();
-            IpAddr::from(ip)
-        }
-        _ => panic!("No IP found in DCC SEND message"),
+        (Some(v4), _) => IpAddr::from(Ipv4Addr::from(v4.as_str().parse::<u32>().ok()?)),
+        (_, Some(v6)) => IpAddr::from(v6.as_str().parse::<Ipv6Addr>().ok()?),
+        _ => return None,
     };
-    irc::DCCSend {
-        filename: captures[1].to_string(),
-        ip,
-        port: captures[4].to_string(),
-        file_size: captures[5].parse::<usize>().unwrap(),
+    // Bots choose the name, so paths are cut down to their last component
+    let filename = captures[1].rsplit(['/', '\\']).next()?.trim();
+    if matches!(filename, "" | "." | "..") {
+        return None;
     }
+    Some(irc::DCCSend {
+        filename: filename.to_owned(),
+        ip,
+        port: captures[4].parse::<u16>().ok()?.to_string(),
+        file_size: captures[5].parse().ok()?,
+    })
+}
+
+/// A file that was saved, and the stream that may still be sending it
+struct Saved {
+    path: PathBuf,
+    crc: String,
+    tee: Option<Tee>,
 }
 
+/// Downloads a file into `destination`, or returns `None` if it was skipped.
+/// The file is also sent to `stream` while it downloads, if there's one. If
+/// the download fails, the partial file is deleted.
 fn download_file(
     request: irc::DCCSend,
-    bar: ProgressBar,
-    directory: impl AsRef<Path>,
-) -> Result<()> {
-    let path = directory.as_ref().join(&request.filename);
-    let mut file = File::create(&path)
+    bar: &ProgressBar,
+    destination: Destination,
+    stream: Option<Stream>,
+) -> Result<Option<Saved>> {
+    let path = match destination.resolve(&request.filename) {
+        Some(path) => path,
+        None => {
+            bar.println(format!(
+                "~ skipping {}, it already exists",
+                request.filename
+            ));
+            bar.finish_and_clear();
+            return Ok(None);
+        }
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| Error::FileCreation(parent.to_string_lossy().to_string(), e))?;
+    }
+    let file = File::create(&path)
         .map_err(|e| Error::FileCreation(path.to_string_lossy().to_string(), e))?;
 
+    let saved = transfer(&request, path.clone(), file, bar, stream);
+    if saved.is_err() {
+        // Otherwise it'd look like a finished download later
+        fs::remove_file(&path).ok();
+    }
+    saved.map(Some)
+}
+
+/// Receives a file offered by `request` into `file`, which is at `path`
+fn transfer(
+    request: &irc::DCCSend,
+    path: PathBuf,
+    mut file: File,
+    bar: &ProgressBar,
+    stream: Option<Stream>,
+) -> Result<Saved> {
     let ip = format!("{}:{}", request.ip, request.port);
-    bar.println(format!("~ downloading {} from {}", request.filename, ip));
-    let mut stream = TcpStream::connect(ip).map_err(Error::Connection)?;
+    bar.println(format!("~ downloading {} from {}", path.display(), ip));
+    let mut connection = TcpStream::connect(ip).map_err(Error::Connection)?;
+
+    let total = request.file_size as u64;
+    let mut tee = stream.and_then(|stream| Tee::start(&stream, &path, total, bar));
 
     let mut buffer = [0; 8192];
     let mut bytes: usize = 0;
+    let mut hasher = crc32fast::Hasher::new();
     while bytes < request.file_size {
-        let count = stream.read(&mut buffer[..])?;
+        let count = connection.read(&mut buffer[..])?;
+        if count == 0 {
+            return Err(Error::Connection(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "the transfer ended after {} of {} bytes",
+                    bytes, request.file_size
+                ),
+            )));
+        }
         file.write_all(&buffer[..count])?;
+        hasher.update(&buffer[..count]);
         bytes += count;
+        if let Some(tee) = &mut tee {
+            tee.write(bytes as u64, bar);
+        }
         bar.set_position(bytes as u64);
     }
     bar.finish_with_message(format!("Done downloading {}", request.filename));
-    stream.shutdown(Shutdown::Both)?;
+    connection.shutdown(Shutdown::Both)?;
     file.flush()?;
-    Ok(())
+    if let Some(tee) = &mut tee {
+        tee.finish(bar);
+    }
+
+    let crc = format!("{:08X}", hasher.finalize());
+    if let Some(expected) = crate::release::ReleaseInfo::parse(&request.filename).crc {
+        if expected != crc {
+            bar.println(format!(
+                "~ {} should have CRC32 {}, but it's {}. It may be corrupted",
+                request.filename, expected, crc
+            ));
+        }
+    }
+    Ok(Saved { path, crc, tee })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dcc_sends() {
+        let send =
+            parse_dcc_send("\x01DCC SEND \"Show - 01.mkv\" 3232235777 5000 1024\x01").unwrap();
+        assert_eq!(send.filename, "Show - 01.mkv");
+        assert_eq!(send.ip, IpAddr::from([192, 168, 1, 1]));
+        assert_eq!(send.port, "5000");
+        assert_eq!(send.file_size, 1024);
+
+        let send = parse_dcc_send("\x01DCC SEND ../../.bashrc ::1 5000 1024\x01").unwrap();
+        assert_eq!(send.filename, ".bashrc");
+        assert_eq!(send.ip, IpAddr::from(Ipv6Addr::LOCALHOST));
+
+        for malformed in [
+            "DCC SEND \"..\" 3232235777 5000 1024",
+            "DCC SEND Show.mkv 99999999999 5000 1024",
+            "DCC SEND Show.mkv 3232235777 99999 1024",
+            "DCC SEND Show.mkv 3232235777 5000 99999999999999999999999",
+            "DCC SEND Show.mkv",
+        ] {
+            assert!(parse_dcc_send(malformed).is_none(), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn truncated_transfers_leave_no_file() {
+        let directory = std::env::temp_dir().join(format!("mahou-dl-{}", std::process::id()));
+        let bot = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = bot.local_addr().unwrap().port();
+        let sending = thread::spawn(move || {
+            let (mut client, _) = bot.accept().unwrap();
+            client.write_all(&[0; 100]).unwrap();
+        });
+
+        let send = irc::DCCSend {
+            filename: "Show - 01.mkv".to_owned(),
+            ip: IpAddr::from(Ipv4Addr::LOCALHOST),
+            port: port.to_string(),
+            file_size: 1000,
+        };
+        let bar = ProgressBar::hidden();
+        let result = download_file(send, &bar, Destination::new(&directory), None);
+        sending.join().unwrap();
+        assert!(
+            matches!(&result, Err(Error::Connection(e)) if e.kind() == io::ErrorKind::UnexpectedEof)
+        );
+        assert!(!directory.join("Show - 01.mkv").exists());
+        fs::remove_dir_all(&directory).ok();
+    }
 }
@@ -0,0 +1,289 @@
+use super::{irc, Error, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How long [`Pool::keep_alive`] waits for messages on each connection
+const IDLE_READ_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How long to wait before connecting again to a server we listen on
+const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// IRC connections kept open between sessions, so that downloading every now
+/// and then doesn't mean connecting and joining channels all over again.
+///
+/// Idle connections must be kept alive by calling [`Pool::keep_alive`] every
+/// so often, otherwise the server closes them for not answering its PINGs.
+/// It can be called from another thread while sessions download.
+#[derive(Default)]
+pub struct Pool {
+    /// Connections sessions left behind, by server
+    links: Mutex<HashMap<String, Link>>,
+    listeners: Mutex<Vec<Listener>>,
+}
+
+/// A channel to stay in, on its own connection that sessions never take, so
+/// that nothing said there is missed while downloading
+struct Listener {
+    config: irc::Config,
+    link: Option<Link>,
+    last_attempt: Option<Instant>,
+}
+
+/// A connection and what's been done on it so far
+pub(crate) struct Link {
+    pub connection: irc::Connection,
+    pub nickname: String,
+    /// Whether the server has pinged us, which means we're registered and can
+    /// join channels
+    pub registered: bool,
+    /// Lowercase, with a leading `#`
+    pub joined_channels: HashSet<String>,
+    /// Channels to stay in, if it's a listener's
+    listening: HashSet<String>,
+    /// Channels from `listening` we've asked to join
+    requested: HashSet<String>,
+}
+
+impl Link {
+    pub fn open(config: &irc::Config, read_timeout: Duration) -> Result<Self> {
+        Ok(Self {
+            connection: irc::Connection::open(config, read_timeout)?,
+            nickname: config.nickname.clone(),
+            registered: false,
+            joined_channels: HashSet::new(),
+            listening: HashSet::new(),
+            requested: HashSet::new(),
+        })
+    }
+
+    /// Joins the channels we listen on, once we can
+    fn join_listening(&mut self) -> Result<()> {
+        if !self.registered {
+            return Ok(());
+        }
+        for channel in &self.listening {
+            if !self.joined_channels.contains(channel) && self.requested.insert(channel.clone()) {
+                self.connection.send(&format!("JOIN {}", channel))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers PINGs and keeps track of joined channels. Returns whether the
+    /// message was one of those.
+    pub fn handle(&mut self, message: &str) -> Result<bool> {
+        if irc::PING_REGEX.is_match(message) {
+            self.connection
+                .send(message.trim_end().replacen("PING", "PONG", 1).as_str())?;
+            self.registered = true;
+            return Ok(true);
+        }
+        if let Some(captures) = irc::JOIN_REGEX.captures(message) {
+            if irc::sender(message).is_some_and(|s| s.eq_ignore_ascii_case(&self.nickname)) {
+                self.joined_channels.insert(captures[1].to_lowercase());
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Reads what arrived on an idle connection, answering PINGs and adding
+    /// the other messages to `messages`. Returns whether it's still open.
+    fn poll(&mut self, server: &str, messages: &mut Vec<(String, String)>) -> bool {
+        self.connection.set_read_timeout(IDLE_READ_TIMEOUT).ok();
+        if self.join_listening().is_err() {
+            return false;
+        }
+        loop {
+            match self.connection.next_message() {
+                Ok(Some(message)) => match self.handle(&message) {
+                    Ok(true) => {}
+                    Ok(false) => messages.push((server.to_owned(), message)),
+                    Err(_) => return false,
+                },
+                Ok(None) => return true,
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+impl Pool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether there are no connections to keep alive
+    pub fn is_empty(&self) -> bool {
+        self.links.lock().unwrap().is_empty()
+            && self
+                .listeners
+                .lock()
+                .unwrap()
+                .iter()
+                .all(|l| l.link.is_none())
+    }
+
+    /// Takes the open connection to `server` out of the pool, if there's one
+    pub(crate) fn take(&self, server: &str) -> Option<Link> {
+        self.links.lock().unwrap().remove(server)
+    }
+
+    /// Puts a connection back, for the next session to use
+    pub(crate) fn put(&self, server: String, link: Link) {
+        self.links.lock().unwrap().insert(server, link);
+    }
+
+    /// Stays in `config`'s channel, connecting again if the connection is
+    /// lost. What's said there is returned by [`Pool::keep_alive`].
+    pub fn listen(&self, config: irc::Config) {
+        self.listeners.lock().unwrap().push(Listener {
+            config,
+            link: None,
+            last_attempt: None,
+        });
+    }
+
+    /// Connects to the channels we listen on that we aren't connected to.
+    /// Each one is tried again at most every [`RECONNECT_DELAY`]. Returns the
+    /// servers we couldn't connect to, and why.
+    pub fn reconnect(&self) -> Vec<(String, Error)> {
+        let mut errors = Vec::new();
+        for listener in self.listeners.lock().unwrap().iter_mut() {
+            if listener.link.is_some()
+                || listener
+                    .last_attempt
+                    .is_some_and(|last| last.elapsed() < RECONNECT_DELAY)
+            {
+                continue;
+            }
+
+            listener.last_attempt = Some(Instant::now());
+            let config = irc::Config {
+                nickname: irc::random_nickname(),
+                ..listener.config.clone()
+            };
+            match Link::open(&config, IDLE_READ_TIMEOUT) {
+                Ok(mut link) => {
+                    let channel = irc::channel_name(&config.channel).to_lowercase();
+                    link.listening.insert(channel);
+                    listener.link = Some(link);
+                }
+                Err(e) => errors.push((config.server, e)),
+            }
+        }
+        errors
+    }
+
+    /// Answers PINGs on every idle connection, and drops the ones that were
+    /// closed. Returns the other messages that arrived, along with the server
+    /// they came from.
+    pub fn keep_alive(&self) -> Vec<(String, String)> {
+        let mut messages = Vec::new();
+        for listener in self.listeners.lock().unwrap().iter_mut() {
+            let server = &listener.config.server;
+            if let Some(link) = &mut listener.link {
+                if !link.poll(server, &mut messages) {
+                    listener.link = None;
+                }
+            }
+        }
+        self.links
+            .lock()
+            .unwrap()
+            .retain(|server, link| link.poll(server, &mut messages));
+        messages
+    }
+
+    /// Says goodbye to every server
+    pub fn close(&self) {
+        for (_, link) in self.links.lock().unwrap().drain() {
+            link.connection.quit().ok();
+        }
+        for listener in self.listeners.lock().unwrap().iter_mut() {
+            if let Some(link) = listener.link.take() {
+                link.connection.quit().ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    #[test]
+    fn link_answers_pings_and_tracks_joins() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = irc::Config {
+            server: server.local_addr().unwrap().to_string(),
+            channel: "#nibl".to_owned(),
+            nickname: "mahou".to_owned(),
+        };
+        let mut link = Link::open(&config, IDLE_READ_TIMEOUT).unwrap();
+        let mut lines = BufReader::new(server.accept().unwrap().0).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "NICK mahou");
+        assert_eq!(lines.next().unwrap().unwrap(), "USER mahou 0 * mahou");
+
+        assert!(!link.registered);
+        assert!(link.handle("PING :irc.example.net\r\n").unwrap());
+        assert!(link.registered);
+        assert_eq!(lines.next().unwrap().unwrap(), "PONG :irc.example.net");
+
+        assert!(link.handle(":mahou!~u@host JOIN :#NIBL\r\n").unwrap());
+        assert!(link.joined_channels.contains("#nibl"));
+        assert!(!link.handle(":someone!~u@host JOIN :#news\r\n").unwrap());
+        assert!(!link.joined_channels.contains("#news"));
+        assert!(!link
+            .handle(":Bot!~u@host PRIVMSG #nibl :Show - 01.mkv\r\n")
+            .unwrap());
+    }
+
+    #[test]
+    fn listeners_keep_their_own_connections() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        // Nothing listens on a port that was just freed
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let unreachable = closed.local_addr().unwrap().to_string();
+        drop(closed);
+
+        let pool = Pool::new();
+        for server in [&unreachable, &address] {
+            pool.listen(irc::Config {
+                server: server.clone(),
+                channel: "#nibl".to_owned(),
+                nickname: "mahou".to_owned(),
+            });
+        }
+        let errors = pool.reconnect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, unreachable);
+        assert!(!pool.is_empty());
+        assert!(pool.take(&address).is_none());
+        // Not tried again until RECONNECT_DELAY has passed
+        assert!(pool.reconnect().is_empty());
+    }
+
+    #[test]
+    fn close_says_goodbye() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let pool = Pool::new();
+        pool.listen(irc::Config {
+            server: server.local_addr().unwrap().to_string(),
+            channel: "#nibl".to_owned(),
+            nickname: "mahou".to_owned(),
+        });
+        assert!(pool.reconnect().is_empty());
+        let lines = BufReader::new(server.accept().unwrap().0).lines();
+        pool.close();
+        assert!(pool.is_empty());
+        let last = lines.map(|line| line.unwrap()).last().unwrap();
+        assert!(last.starts_with("QUIT"), "{}", last);
+    }
+}
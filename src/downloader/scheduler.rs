@@ -0,0 +1,312 @@
+use super::irc;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// How long a bot has to start sending a package before we give up on it, so
+/// that packages it refused or queued don't hold a slot forever
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Maximum number of concurrent DCC transfers. `None` means unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    pub total: Option<usize>,
+    pub per_bot: Option<usize>,
+    /// Overrides `per_bot` for specific bots
+    pub bots: HashMap<String, usize>,
+}
+
+impl Limits {
+    fn for_bot(&self, bot: &str) -> Option<usize> {
+        self.bots
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(bot))
+            .map(|(_, &limit)| limit)
+            .or(self.per_bot)
+    }
+}
+
+/// A per-bot limit, written as `BOT=N`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BotLimit {
+    pub bot: String,
+    pub limit: usize,
+}
+
+impl FromStr for BotLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid bot limit {} (expected BOT=N)", s);
+        let (bot, limit) = s.split_once('=').ok_or_else(invalid)?;
+        Ok(Self {
+            bot: bot.trim().to_owned(),
+            limit: match limit.trim().parse() {
+                Ok(0) => return Err(format!("Invalid bot limit {} (must be at least 1)", s)),
+                Ok(limit) => limit,
+                Err(_) => return Err(invalid()),
+            },
+        })
+    }
+}
+
+/// Parses a transfer limit, which can't be 0 since nothing would ever be
+/// downloaded
+pub fn parse_limit(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("The transfer limit must be at least 1".to_owned()),
+        Ok(limit) => Ok(limit),
+        Err(e) => Err(format!("Invalid transfer limit {}: {}", s, e)),
+    }
+}
+
+/// Keeps track of which packages have been requested over a connection, and
+/// only hands out the next one when the bot (and the session as a whole, which
+/// may span several connections) has a free slot
+pub(crate) struct Scheduler<'l> {
+    limits: &'l Limits,
+    queue: VecDeque<Queued>,
+    active: HashMap<String, usize>,
+    active_total: &'l AtomicUsize,
+    /// Packages requested that the bot hasn't started sending yet
+    waiting: Vec<Waiting>,
+    started: Instant,
+    /// [`REQUEST_TIMEOUT`], unless testing
+    timeout: Duration,
+}
+
+struct Waiting {
+    bot: String,
+    package: String,
+    since: Instant,
+}
+
+struct Queued {
+    channel: String,
+    bot: String,
+    package: String,
+}
+
+impl<'l> Scheduler<'l> {
+    pub fn new(limits: &'l Limits, active_total: &'l AtomicUsize, batches: &[irc::Batch]) -> Self {
+        let queue = batches
+            .iter()
+            .flat_map(|b| {
+                b.packages.iter().map(|p| Queued {
+                    channel: irc::channel_name(&b.channel).to_lowercase(),
+                    bot: b.bot.clone(),
+                    package: p.clone(),
+                })
+            })
+            .collect();
+        Self {
+            limits,
+            queue,
+            active: HashMap::new(),
+            active_total,
+            waiting: Vec::new(),
+            started: Instant::now(),
+            timeout: REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Takes the next package that can be requested right now, as a
+    /// `(bot, package)` pair, and counts it as an active transfer. Bots are
+    /// only asked for packages after we've joined their channel.
+    pub fn next(&mut self, joined_channels: &HashSet<String>) -> Option<(String, String)> {
+        let index = self.queue.iter().position(|q| {
+            let active = self.active.get(&q.bot.to_lowercase()).copied().unwrap_or(0);
+            joined_channels.contains(&q.channel)
+                && !matches!(self.limits.for_bot(&q.bot), Some(max) if active >= max)
+        })?;
+
+        // Reserve a slot in the whole session
+        let total = self.limits.total;
+        self.active_total
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| match total {
+                Some(max) if n >= max => None,
+                _ => Some(n + 1),
+            })
+            .ok()?;
+
+        let Queued { bot, package, .. } = self.queue.remove(index)?;
+        *self.active.entry(bot.to_lowercase()).or_default() += 1;
+        self.waiting.push(Waiting {
+            bot: bot.clone(),
+            package: package.clone(),
+            since: Instant::now(),
+        });
+        Some((bot, package))
+    }
+
+    /// Called when `bot` starts sending a file. Returns the package it's most
+    /// likely sending, the first one we asked it for, or `None` if we weren't
+    /// waiting for one, like when we already gave up on it.
+    pub fn start(&mut self, bot: &str) -> Option<String> {
+        let index = self
+            .waiting
+            .iter()
+            .position(|w| w.bot.eq_ignore_ascii_case(bot))?;
+        Some(self.waiting.remove(index).package)
+    }
+
+    /// Gives up on the packages that haven't started within
+    /// [`REQUEST_TIMEOUT`], freeing their slots. Returns them as
+    /// `(bot, package)` pairs.
+    pub fn expire(&mut self) -> Vec<(String, String)> {
+        let (expired, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.waiting)
+            .into_iter()
+            .partition(|w| w.since.elapsed() >= self.timeout);
+        self.waiting = waiting;
+        expired
+            .into_iter()
+            .map(|Waiting { bot, package, .. }| {
+                self.finish(&bot);
+                (bot, package)
+            })
+            .collect()
+    }
+
+    /// Gives up on the queued packages whose channel still isn't joined
+    /// [`REQUEST_TIMEOUT`] after starting, like when it doesn't exist or
+    /// needs a registered nickname. Returns them as `(channel, bot, package)`.
+    pub fn expire_unjoined(
+        &mut self,
+        joined_channels: &HashSet<String>,
+    ) -> Vec<(String, String, String)> {
+        if self.started.elapsed() < self.timeout {
+            return Vec::new();
+        }
+        let (unjoined, queue): (VecDeque<_>, VecDeque<_>) = std::mem::take(&mut self.queue)
+            .into_iter()
+            .partition(|q| !joined_channels.contains(&q.channel));
+        self.queue = queue;
+        unjoined
+            .into_iter()
+            .map(|q| (q.channel, q.bot, q.package))
+            .collect()
+    }
+
+    /// Frees the slot of a transfer from `bot`
+    pub fn finish(&mut self, bot: &str) {
+        if let Some(active) = self.active.get_mut(&bot.to_lowercase()) {
+            if *active > 0 {
+                *active -= 1;
+                self.active_total.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+impl Drop for Scheduler<'_> {
+    /// Frees the session's slots, even if the connection failed midway
+    fn drop(&mut self) {
+        let active: usize = self.active.values().sum();
+        self.active_total.fetch_sub(active, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bot_limits() {
+        assert_eq!(
+            "CR-HOLLAND|NEW = 2".parse(),
+            Ok(BotLimit {
+                bot: "CR-HOLLAND|NEW".to_owned(),
+                limit: 2
+            })
+        );
+        assert!("Bot=0".parse::<BotLimit>().is_err());
+        assert!("Bot".parse::<BotLimit>().is_err());
+        assert!(parse_limit("0").is_err());
+        assert_eq!(parse_limit("3"), Ok(3));
+    }
+
+    #[test]
+    fn limits_slots_per_bot() {
+        let limits = Limits {
+            per_bot: Some(1),
+            ..Default::default()
+        };
+        let active_total = AtomicUsize::new(0);
+        let batches = [irc::Batch {
+            channel: "nibl".to_owned(),
+            bot: "Bot".to_owned(),
+            packages: vec!["1".to_owned(), "2".to_owned()],
+        }];
+        let mut scheduler = Scheduler::new(&limits, &active_total, &batches);
+        let joined = HashSet::from(["#nibl".to_owned()]);
+
+        assert_eq!(scheduler.next(&joined), Some(("Bot".into(), "1".into())));
+        assert_eq!(scheduler.next(&joined), None);
+        assert_eq!(scheduler.start("bot"), Some("1".into()));
+        assert_eq!(scheduler.start("bot"), None);
+        scheduler.finish("Bot");
+        assert_eq!(scheduler.next(&joined), Some(("Bot".into(), "2".into())));
+        assert!(scheduler.expire().is_empty());
+    }
+
+    #[test]
+    fn frees_slots_when_dropped() {
+        let limits = Limits {
+            total: Some(2),
+            ..Default::default()
+        };
+        let active_total = AtomicUsize::new(0);
+        let batches = [irc::Batch {
+            channel: "#nibl".to_owned(),
+            bot: "Bot".to_owned(),
+            packages: vec!["1".to_owned(), "2".to_owned()],
+        }];
+        let joined = HashSet::from(["#nibl".to_owned()]);
+        let mut failed = Scheduler::new(&limits, &active_total, &batches);
+        assert!(failed.next(&joined).is_some());
+        assert!(failed.next(&joined).is_some());
+        failed.finish("Bot");
+        assert_eq!(active_total.load(Ordering::SeqCst), 1);
+
+        // Another network waits for the slot
+        let mut other = Scheduler::new(&limits, &active_total, &batches);
+        assert!(other.next(&joined).is_some());
+        assert_eq!(other.next(&joined), None);
+        drop(failed);
+        assert_eq!(other.next(&joined), Some(("Bot".into(), "2".into())));
+        drop(other);
+        assert_eq!(active_total.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn gives_up_on_channels_never_joined() {
+        let limits = Limits::default();
+        let active_total = AtomicUsize::new(0);
+        let batches = [
+            irc::Batch {
+                channel: "#nibl".to_owned(),
+                bot: "Bot".to_owned(),
+                packages: vec!["1".to_owned()],
+            },
+            irc::Batch {
+                channel: "#private".to_owned(),
+                bot: "Other".to_owned(),
+                packages: vec!["2".to_owned()],
+            },
+        ];
+        let joined = HashSet::from(["#nibl".to_owned()]);
+        let mut scheduler = Scheduler::new(&limits, &active_total, &batches);
+        assert!(scheduler.expire_unjoined(&joined).is_empty());
+
+        scheduler.timeout = Duration::ZERO;
+        assert_eq!(
+            scheduler.expire_unjoined(&joined),
+            vec![("#private".into(), "Other".into(), "2".into())]
+        );
+        assert_eq!(scheduler.next(&joined), Some(("Bot".into(), "1".into())));
+        assert_eq!(scheduler.next(&joined), None);
+    }
+}
@@ -0,0 +1,118 @@
+use super::{connect_and_download, irc, Limits, OnComplete, Pool, Result, Stream};
+use crate::{finder::Entry, library::Destination};
+use indicatif::MultiProgress;
+use std::{sync::atomic::AtomicUsize, thread};
+
+/// Downloads entries from any number of bots and networks in one go. Entries
+/// are grouped by network and bot, and each network gets a single IRC
+/// connection. All transfers share the same progress display and limits.
+pub struct Session<'d> {
+    destination: &'d Destination,
+    limits: &'d Limits,
+    networks: Vec<(irc::Config, Vec<irc::Batch>)>,
+    on_complete: Option<OnComplete<'d>>,
+    pool: Option<&'d Pool>,
+    stream: Option<Stream>,
+}
+
+impl<'d> Session<'d> {
+    pub fn new(destination: &'d Destination, limits: &'d Limits) -> Self {
+        Self {
+            destination,
+            limits,
+            networks: Vec::new(),
+            on_complete: None,
+            pool: None,
+            stream: None,
+        }
+    }
+
+    /// Also sends each file to `stream` while it downloads
+    pub fn with_stream(mut self, stream: Option<Stream>) -> Self {
+        self.stream = stream;
+        self
+    }
+
+    /// Reuses the connections in `pool`, and leaves them there once done
+    /// instead of disconnecting
+    pub fn with_pool(mut self, pool: &'d Pool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Calls `on_complete` as soon as each file is downloaded, like to keep a
+    /// history of downloads
+    pub fn on_complete(mut self, on_complete: OnComplete<'d>) -> Self {
+        self.on_complete = Some(on_complete);
+        self
+    }
+
+    /// Queues an entry for download from its network
+    pub fn add(&mut self, entry: &Entry) {
+        let config = &entry.network;
+        let batches = match self
+            .networks
+            .iter_mut()
+            .find(|(c, _)| c.server == config.server)
+        {
+            Some((_, batches)) => batches,
+            None => {
+                self.networks.push((config.as_ref().clone(), Vec::new()));
+                &mut self.networks.last_mut().unwrap().1
+            }
+        };
+
+        let package = entry.package_number.to_string();
+        match batches
+            .iter_mut()
+            .find(|b| b.bot == entry.bot_name && b.channel == config.channel)
+        {
+            Some(batch) => batch.packages.push(package),
+            None => batches.push(irc::Batch {
+                channel: config.channel.clone(),
+                bot: entry.bot_name.clone(),
+                packages: vec![package],
+            }),
+        }
+    }
+
+    /// Connects to every network and downloads everything that was queued.
+    /// A failing network doesn't interrupt the others, but its error is
+    /// returned once they're done.
+    pub fn run(self) -> Result<()> {
+        let multibar = MultiProgress::new();
+        let active_total = AtomicUsize::new(0);
+        let (destination, limits, on_complete, pool) =
+            (self.destination, self.limits, self.on_complete, self.pool);
+
+        let results: Vec<Result<()>> = thread::scope(|s| {
+            let handles: Vec<_> = self
+                .networks
+                .into_iter()
+                .map(|(config, batches)| {
+                    let (multibar, active_total) = (&multibar, &active_total);
+                    let stream = self.stream.clone();
+                    s.spawn(move || {
+                        let request = irc::Request {
+                            config,
+                            batches,
+                            destination,
+                            stream,
+                        };
+                        connect_and_download(
+                            request,
+                            limits,
+                            multibar,
+                            active_total,
+                            on_complete,
+                            pool,
+                        )
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        results.into_iter().collect()
+    }
+}
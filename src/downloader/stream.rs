@@ -0,0 +1,508 @@
+//! Sending a file somewhere while it downloads, besides saving it, so that it
+//! can be watched right away.
+
+use indicatif::ProgressBar;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How much of a file is downloaded before the player is started
+pub const PLAY_BUFFER: u64 = 16 * 1024 * 1024;
+
+/// How often readers check for more of the file, and the server for new
+/// clients
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a finished download stays up over HTTP for something to play it,
+/// when nothing has yet
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(60 * 10);
+
+/// Players reconnect to seek, so the stream stays up for a bit after the last
+/// client leaves
+const LINGER: Duration = Duration::from_secs(5);
+
+/// Where to send files while they download
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stream {
+    /// Writes the file to stdout, like to pipe it into a player
+    Stdout,
+    /// Serves the file over HTTP on localhost, on `port` or any free port if
+    /// it's 0. If there's a `player` command, it's started with the file's URL
+    /// once [`PLAY_BUFFER`] bytes are downloaded.
+    Http {
+        port: u16,
+        player: Option<Vec<String>>,
+    },
+}
+
+impl FromStr for Stream {
+    type Err = String;
+
+    /// `stdout`, `http` or `http:PORT`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "stdout" | "-" => Ok(Self::Stdout),
+            "http" => Ok(Self::Http {
+                port: 0,
+                player: None,
+            }),
+            other => match other.strip_prefix("http:").map(str::parse) {
+                Some(Ok(port)) => Ok(Self::Http { port, player: None }),
+                _ => Err(format!(
+                    "Invalid stream {} (expected stdout, http or http:PORT)",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// What's shared between a download and whoever reads it, from stdout's
+/// writer thread or over HTTP
+pub(crate) struct Shared {
+    path: PathBuf,
+    total: u64,
+    written: AtomicU64,
+    /// The download finished or failed, so there won't be more bytes
+    done: AtomicBool,
+    /// The stream is over, so the server stops taking new clients
+    closed: AtomicBool,
+    clients: AtomicUsize,
+    /// Someone connected to the server at some point
+    served: AtomicBool,
+}
+
+impl Shared {
+    fn new(path: &Path, total: u64) -> Self {
+        Self {
+            path: path.to_owned(),
+            total,
+            written: AtomicU64::new(0),
+            done: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            clients: AtomicUsize::new(0),
+            served: AtomicBool::new(false),
+        }
+    }
+}
+
+/// A stream started for one file. Readers get the file from disk as it's
+/// saved, on their own threads, so a slow or paused reader never holds up the
+/// download.
+pub(crate) enum Tee {
+    Stdout {
+        shared: Arc<Shared>,
+        writer: Option<JoinHandle<()>>,
+    },
+    Http {
+        shared: Arc<Shared>,
+        url: String,
+        player: Option<Vec<String>>,
+        child: Option<Child>,
+    },
+}
+
+impl Tee {
+    /// Starts streaming the file that's being downloaded to `path`, which will
+    /// have `total` bytes
+    pub fn start(stream: &Stream, path: &Path, total: u64, bar: &ProgressBar) -> Option<Self> {
+        let (port, player) = match stream {
+            Stream::Stdout => {
+                let shared = Arc::new(Shared::new(path, total));
+                let reader = shared.clone();
+                let bar = bar.clone();
+                let writer = thread::spawn(move || {
+                    let mut stdout = io::stdout().lock();
+                    if let Err(e) = copy(&reader, 0, &mut stdout).and_then(|_| stdout.flush()) {
+                        bar.println(format!("~ stopped streaming to stdout: {}", e));
+                    }
+                });
+                return Some(Self::Stdout {
+                    shared,
+                    writer: Some(writer),
+                });
+            }
+            Stream::Http { port, player } => (*port, player.clone()),
+        };
+
+        let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+            Ok(listener) => listener,
+            Err(e) => {
+                bar.println(format!("~ couldn't stream on port {}: {}", port, e));
+                return None;
+            }
+        };
+        let mut url =
+            reqwest::Url::parse(&format!("http://{}/", listener.local_addr().ok()?)).ok()?;
+        if let Some(name) = path.file_name() {
+            url.path_segments_mut().ok()?.push(&name.to_string_lossy());
+        }
+        bar.println(format!("~ streaming at {}", url));
+
+        let shared = Arc::new(Shared::new(path, total));
+        if let Err(e) = listener.set_nonblocking(true) {
+            bar.println(format!("~ couldn't stream on port {}: {}", port, e));
+            return None;
+        }
+        let server = shared.clone();
+        thread::spawn(move || accept(listener, server));
+
+        Some(Self::Http {
+            shared,
+            url: url.into(),
+            player,
+            child: None,
+        })
+    }
+
+    fn shared(&self) -> &Shared {
+        match self {
+            Self::Stdout { shared, .. } | Self::Http { shared, .. } => shared,
+        }
+    }
+
+    /// Called after a chunk is saved, which makes `written` bytes so far
+    pub fn write(&mut self, written: u64, bar: &ProgressBar) {
+        let shared = self.shared();
+        shared.written.store(written, Ordering::SeqCst);
+        if written >= PLAY_BUFFER.min(shared.total) {
+            self.play(bar);
+        }
+    }
+
+    /// Starts the player, unless it's already running
+    fn play(&mut self, bar: &ProgressBar) {
+        if let Self::Http {
+            url,
+            player: Some(command),
+            child: child @ None,
+            ..
+        } = self
+        {
+            let (program, args) = match command.split_first() {
+                Some(split) => split,
+                None => return,
+            };
+            bar.println(format!("~ playing with {}", program));
+            let spawned = Command::new(program)
+                .args(args)
+                .arg(url.as_str())
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn();
+            match spawned {
+                Ok(spawned) => *child = Some(spawned),
+                Err(e) => {
+                    bar.println(format!("~ couldn't start {}: {}", program, e));
+                    // Don't try again on every chunk
+                    command.clear();
+                }
+            }
+        }
+    }
+
+    /// Called once the whole file is saved, so readers get the rest of it
+    pub fn finish(&mut self, bar: &ProgressBar) {
+        let shared = self.shared();
+        shared.written.store(shared.total, Ordering::SeqCst);
+        shared.done.store(true, Ordering::SeqCst);
+        self.play(bar);
+    }
+
+    /// Waits for stdout to take the whole file, for the player to close, or
+    /// for whoever is reading the file over HTTP to finish. If nobody has
+    /// connected yet, the file is served until someone has, or for up to
+    /// [`CLIENT_TIMEOUT`]. Then the stream is closed.
+    pub fn wait(mut self, bar: &ProgressBar) {
+        match &mut self {
+            Self::Stdout { writer, .. } => {
+                if let Some(writer) = writer.take() {
+                    writer.join().ok();
+                }
+            }
+            Self::Http {
+                child: Some(child), ..
+            } => {
+                bar.println("~ downloaded, waiting for the player to close");
+                child.wait().ok();
+            }
+            Self::Http { shared, url, .. } => {
+                if !shared.served.load(Ordering::SeqCst) {
+                    bar.println(format!("~ downloaded, still streaming at {}", url));
+                }
+                if !wait_for_clients(shared, LINGER, CLIENT_TIMEOUT) {
+                    bar.println("~ nothing played the stream, stopping it");
+                }
+            }
+        }
+    }
+}
+
+/// Waits until nobody has been connected for `linger`, after someone has, or
+/// for `timeout` if nobody connects. Returns whether anybody was served.
+fn wait_for_clients(shared: &Shared, linger: Duration, timeout: Duration) -> bool {
+    let started = Instant::now();
+    let mut idle = Instant::now();
+    loop {
+        if shared.clients.load(Ordering::SeqCst) > 0 {
+            idle = Instant::now();
+        } else if shared.served.load(Ordering::SeqCst) {
+            if idle.elapsed() >= linger {
+                return true;
+            }
+        } else if started.elapsed() >= timeout {
+            return false;
+        }
+        thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+impl Drop for Tee {
+    /// Readers stop waiting for more of a failed download, and the server
+    /// stops
+    fn drop(&mut self) {
+        let shared = self.shared();
+        shared.done.store(true, Ordering::SeqCst);
+        shared.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Serves the file to every client of `listener`, which is nonblocking, until
+/// the stream is closed
+fn accept(listener: TcpListener, shared: Arc<Shared>) {
+    while !shared.closed.load(Ordering::SeqCst) {
+        let client = match listener.accept() {
+            Ok((client, _)) => client,
+            Err(_) => {
+                thread::sleep(WAIT_INTERVAL);
+                continue;
+            }
+        };
+        if client.set_nonblocking(false).is_err() {
+            continue;
+        }
+        // Counted right away, so that nobody thinks the stream is over
+        // before the client is served
+        shared.clients.fetch_add(1, Ordering::SeqCst);
+        shared.served.store(true, Ordering::SeqCst);
+        let shared = shared.clone();
+        thread::spawn(move || {
+            serve(client, &shared).ok();
+            shared.clients.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Answers a single HTTP request for the file, waiting for it to download as
+/// needed
+fn serve(client: TcpStream, shared: &Shared) -> io::Result<()> {
+    let mut reader = BufReader::new(client.try_clone()?);
+    let mut client = client;
+
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let mut start = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some(range) = line.to_lowercase().trim().strip_prefix("range: bytes=") {
+            start = range
+                .split('-')
+                .next()
+                .and_then(|start| start.parse().ok())
+                .unwrap_or(0);
+        }
+    }
+
+    let total = shared.total;
+    // Players look for an index at the end of the file, which isn't there yet.
+    // Saying so right away beats making them wait for the whole download.
+    if start > 0 && start >= shared.written.load(Ordering::SeqCst) {
+        return client.write_all(
+            format!(
+                "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nConnection: close\r\n\r\n",
+                total
+            )
+            .as_bytes(),
+        );
+    }
+
+    let status = match start {
+        0 => "HTTP/1.1 200 OK\r\n".to_owned(),
+        _ => format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n",
+            start,
+            total.saturating_sub(1),
+            total
+        ),
+    };
+    write!(
+        client,
+        "{}Content-Length: {}\r\nContent-Type: {}\r\nAccept-Ranges: bytes\r\nConnection: close\r\n\r\n",
+        status,
+        total - start,
+        content_type(&shared.path)
+    )?;
+    if request.starts_with("HEAD") {
+        return Ok(());
+    }
+    copy(shared, start, &mut client)
+}
+
+/// Writes the file from `start` to `out` as it's saved, until it's all there
+/// or the download fails
+fn copy(shared: &Shared, start: u64, out: &mut impl Write) -> io::Result<()> {
+    let total = shared.total;
+    let mut file = File::open(&shared.path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let mut buffer = vec![0; 64 * 1024];
+    let mut offset = start;
+    while offset < total {
+        let written = shared.written.load(Ordering::SeqCst);
+        if offset >= written {
+            if shared.done.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            thread::sleep(WAIT_INTERVAL);
+            continue;
+        }
+        let wanted = (written - offset).min(buffer.len() as u64) as usize;
+        let count = file.read(&mut buffer[..wanted])?;
+        if count == 0 {
+            thread::sleep(WAIT_INTERVAL);
+            continue;
+        }
+        out.write_all(&buffer[..count])?;
+        offset += count as u64;
+    }
+    Ok(())
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("mkv") => "video/x-matroska",
+        Some("mp4") => "video/mp4",
+        Some("avi") => "video/x-msvideo",
+        Some("webm") => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streams() {
+        assert_eq!("stdout".parse(), Ok(Stream::Stdout));
+        assert_eq!("-".parse(), Ok(Stream::Stdout));
+        assert_eq!(
+            "HTTP".parse(),
+            Ok(Stream::Http {
+                port: 0,
+                player: None
+            })
+        );
+        assert_eq!(
+            "http:8080".parse(),
+            Ok(Stream::Http {
+                port: 8080,
+                player: None
+            })
+        );
+        for invalid in ["", "http:", "http:port", "http:65536", "ftp"] {
+            assert!(invalid.parse::<Stream>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    /// Sends `request` to `serve`, and returns the response once the file is
+    /// saved up to `then_written` bytes
+    fn respond(shared: &Arc<Shared>, request: &str, then_written: u64) -> String {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let server = shared.clone();
+        let serving = thread::spawn(move || serve(listener.accept().unwrap().0, &server));
+
+        client.write_all(request.as_bytes()).unwrap();
+        thread::sleep(WAIT_INTERVAL * 2);
+        shared.written.store(then_written, Ordering::SeqCst);
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        serving.join().unwrap().unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_partial_files() {
+        let path = std::env::temp_dir().join(format!("mahou-stream-{}.mkv", std::process::id()));
+        let file: String = ('a'..='z').cycle().take(100).collect();
+        std::fs::write(&path, &file).unwrap();
+        let shared = Arc::new(Shared::new(&path, 100));
+        shared.written.store(60, Ordering::SeqCst);
+
+        let response = respond(&shared, "GET /show.mkv HTTP/1.1\r\n\r\n", 100);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("Content-Length: 100\r\n"));
+        assert!(response.contains("Content-Type: video/x-matroska\r\n"));
+        assert!(response.ends_with(&format!("\r\n\r\n{}", file)));
+
+        shared.written.store(60, Ordering::SeqCst);
+        let response = respond(&shared, "GET / HTTP/1.1\r\nRange: bytes=40-\r\n\r\n", 100);
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(response.contains("Content-Range: bytes 40-99/100\r\n"));
+        assert!(response.contains("Content-Length: 60\r\n"));
+        assert!(response.ends_with(&format!("\r\n\r\n{}", &file[40..])));
+
+        // Past what's saved, like players looking for an index at the end
+        shared.written.store(60, Ordering::SeqCst);
+        let response = respond(&shared, "GET / HTTP/1.1\r\nRange: bytes=80-\r\n\r\n", 100);
+        assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(response.contains("Content-Range: bytes */100\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        // A failed download ends the response early
+        shared.written.store(60, Ordering::SeqCst);
+        shared.done.store(true, Ordering::SeqCst);
+        let response = respond(&shared, "GET / HTTP/1.1\r\n\r\n", 60);
+        assert!(response.ends_with(&format!("\r\n\r\n{}", &file[..60])));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn waits_for_someone_to_play_it() {
+        let linger = WAIT_INTERVAL * 2;
+        let shared = Arc::new(Shared::new(Path::new("show.mkv"), 100));
+        assert!(!wait_for_clients(&shared, linger, WAIT_INTERVAL * 2));
+
+        let client = shared.clone();
+        let playing = thread::spawn(move || {
+            thread::sleep(WAIT_INTERVAL * 4);
+            client.clients.fetch_add(1, Ordering::SeqCst);
+            client.served.store(true, Ordering::SeqCst);
+            thread::sleep(WAIT_INTERVAL * 4);
+            client.clients.fetch_sub(1, Ordering::SeqCst);
+        });
+        let started = Instant::now();
+        assert!(wait_for_clients(&shared, linger, Duration::from_secs(60)));
+        // Not before the client was done with it
+        assert!(started.elapsed() >= WAIT_INTERVAL * 8);
+        playing.join().unwrap();
+    }
+}
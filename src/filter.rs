@@ -0,0 +1,383 @@
+//! A small language for filtering results, like
+//! `SubsPlease|Erai-raws -720p "some phrase" /v\d/ size:<2G ep:>=5`.
+//!
+//! Every term must match. Terms can be:
+//! - words or `"quoted phrases"`, found anywhere in the name, bot or source
+//! - `/regexes/`, matched against the name
+//! - `field:value`, compared to what's parsed from the name. Fields are
+//!   `group`, `res`, `codec`, `bot`, `source`, and the numeric `size` and `ep`,
+//!   which also take `<`, `<=`, `>` and `>=`
+//!
+//! A term starting with `-` must not match, and `a|b` matches either.
+
+use crate::{finder::Entry, release::ReleaseInfo};
+use regex::Regex;
+use std::{iter::Peekable, str::Chars, str::FromStr};
+
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone)]
+struct Term {
+    negated: bool,
+    /// Any of them can match
+    alternatives: Vec<Atom>,
+}
+
+#[derive(Debug, Clone)]
+enum Atom {
+    /// Lowercase
+    Text(String),
+    Regex(Regex),
+    Field(Field, Condition),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Group,
+    Resolution,
+    Codec,
+    Bot,
+    Source,
+    Size,
+    Episode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    /// Lowercase
+    Is(String),
+    Compare(Comparison, i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        let text = format!("{} {} {}", entry.name, entry.bot_name, entry.source).to_lowercase();
+        let release = ReleaseInfo::parse(&entry.name);
+        self.terms.iter().all(|term| {
+            let found = term
+                .alternatives
+                .iter()
+                .any(|atom| atom.matches(entry, &release, &text));
+            found != term.negated
+        })
+    }
+}
+
+/// What `mahou latest` keeps of Nibl's feed of new releases
+#[derive(Debug, Clone, Default)]
+pub struct FeedFilter {
+    pub resolution: Option<String>,
+    pub group: Option<String>,
+    pub filter: Option<Filter>,
+}
+
+impl FeedFilter {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        let release = ReleaseInfo::parse(&entry.name);
+        let same = |wanted: &Option<String>, found: &Option<String>| match wanted {
+            Some(wanted) => found
+                .as_deref()
+                .is_some_and(|found| found.eq_ignore_ascii_case(wanted)),
+            None => true,
+        };
+        same(&self.resolution, &release.resolution)
+            && same(&self.group, &release.group)
+            && self.filter.as_ref().is_none_or(|f| f.matches(entry))
+    }
+
+    /// Whether it leaves anything out. Downloading the whole feed would
+    /// mean downloading whatever anyone uploaded lately.
+    pub fn narrows(&self) -> bool {
+        self.resolution.is_some()
+            || self.group.is_some()
+            || self.filter.as_ref().is_some_and(|f| !f.terms.is_empty())
+    }
+}
+
+impl Atom {
+    fn matches(&self, entry: &Entry, release: &ReleaseInfo, text: &str) -> bool {
+        let is =
+            |value: &str, found: Option<&str>| found.is_some_and(|f| f.eq_ignore_ascii_case(value));
+        match self {
+            Self::Text(words) => text.contains(words.as_str()),
+            Self::Regex(regex) => regex.is_match(&entry.name),
+            Self::Field(field, Condition::Is(value)) => match field {
+                Field::Group => is(value, release.group.as_deref()),
+                Field::Resolution => is(value, release.resolution.as_deref()),
+                Field::Codec => {
+                    is(value, release.video_codec.as_deref())
+                        || is(value, release.audio_codec.as_deref())
+                }
+                Field::Bot => is(value, Some(&entry.bot_name)),
+                Field::Source => is(value, Some(&entry.source)),
+                Field::Size | Field::Episode => false,
+            },
+            Self::Field(field, Condition::Compare(comparison, value)) => {
+                let found = match field {
+                    Field::Size => entry.size_bytes.map(|size| size as i64),
+                    Field::Episode => entry.episode.or(release.episode).map(i64::from),
+                    _ => None,
+                };
+                found.is_some_and(|found| comparison.holds(found, *value))
+            }
+        }
+    }
+}
+
+impl Comparison {
+    fn holds(self, left: i64, right: i64) -> bool {
+        match self {
+            Self::Less => left < right,
+            Self::LessOrEqual => left <= right,
+            Self::Equal => left == right,
+            Self::GreaterOrEqual => left >= right,
+            Self::Greater => left > right,
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars().peekable();
+        let mut terms = Vec::new();
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.peek().is_none() {
+                break;
+            }
+
+            // A lone - is just text
+            let negated = chars.peek() == Some(&'-') && {
+                let mut ahead = chars.clone();
+                ahead.next();
+                ahead.peek().is_some_and(|c| !c.is_whitespace())
+            };
+            if negated {
+                chars.next();
+            }
+
+            let mut alternatives = vec![parse_atom(&mut chars)?];
+            while chars.next_if_eq(&'|').is_some() {
+                alternatives.push(parse_atom(&mut chars)?);
+            }
+            alternatives.retain(|atom| !matches!(atom, Atom::Text(text) if text.is_empty()));
+            if !alternatives.is_empty() {
+                terms.push(Term {
+                    negated,
+                    alternatives,
+                });
+            }
+        }
+        Ok(Self { terms })
+    }
+}
+
+fn parse_atom(chars: &mut Peekable<Chars>) -> Result<Atom, String> {
+    match chars.peek() {
+        Some('"') => {
+            chars.next();
+            Ok(Atom::Text(read_quoted(chars).to_lowercase()))
+        }
+        Some('/') => {
+            chars.next();
+            let mut pattern = String::new();
+            loop {
+                match chars.next() {
+                    Some('/') => break,
+                    Some('\\') if chars.peek() == Some(&'/') => pattern.push(chars.next().unwrap()),
+                    Some(c) => pattern.push(c),
+                    None => return Err(format!("Unterminated regex /{}", pattern)),
+                }
+            }
+            Regex::new(&format!("(?i){}", pattern))
+                .map(Atom::Regex)
+                .map_err(|e| e.to_string())
+        }
+        _ => {
+            let mut word = String::new();
+            while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && c != '|') {
+                word.push(c);
+                if c == ':' {
+                    break;
+                }
+            }
+            let field =
+                word.strip_suffix(':')
+                    .and_then(|name| match name.to_lowercase().as_str() {
+                        "group" => Some(Field::Group),
+                        "res" => Some(Field::Resolution),
+                        "codec" => Some(Field::Codec),
+                        "bot" => Some(Field::Bot),
+                        "source" => Some(Field::Source),
+                        "size" => Some(Field::Size),
+                        "ep" => Some(Field::Episode),
+                        _ => None,
+                    });
+            match field {
+                Some(field) => {
+                    let value = match chars.next_if_eq(&'"') {
+                        Some(_) => read_quoted(chars),
+                        None => read_word(chars),
+                    };
+                    Ok(Atom::Field(field, parse_condition(field, &value)?))
+                }
+                // Things like "Re:Zero" aren't fields
+                None => {
+                    word += &read_word(chars);
+                    Ok(Atom::Text(word.to_lowercase()))
+                }
+            }
+        }
+    }
+}
+
+fn parse_condition(field: Field, value: &str) -> Result<Condition, String> {
+    if !matches!(field, Field::Size | Field::Episode) {
+        let value = value.to_lowercase();
+        // 1080 is as good as 1080p
+        return Ok(match field {
+            Field::Resolution if value.chars().all(|c| c.is_ascii_digit()) => {
+                Condition::Is(value + "p")
+            }
+            _ => Condition::Is(value),
+        });
+    }
+
+    let (comparison, number) = [
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+        ("=", Comparison::Equal),
+    ]
+    .into_iter()
+    .find_map(|(op, comparison)| Some((comparison, value.strip_prefix(op)?)))
+    .unwrap_or((Comparison::Equal, value));
+
+    let number = match field {
+        Field::Size => crate::finder::parse_size(number).map(|size| size as i64),
+        _ => number.parse().ok(),
+    };
+    number
+        .map(|number| Condition::Compare(comparison, number))
+        .ok_or_else(|| format!("Invalid value {}", value))
+}
+
+/// Reads until the closing quote, which may be missing
+fn read_quoted(chars: &mut Peekable<Chars>) -> String {
+    chars.by_ref().take_while(|&c| c != '"').collect()
+}
+
+fn read_word(chars: &mut Peekable<Chars>) -> String {
+    let mut word = String::new();
+    while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && c != '|') {
+        word.push(c);
+    }
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters() {
+        let entries = [
+            Entry::for_tests(
+                "[SubsPlease] Frieren - 05 (1080p) [ABCD1234].mkv",
+                "CR-HOLLAND|NEW",
+                "1.4G",
+            ),
+            Entry::for_tests(
+                "[SubsPlease] Frieren - 05 (720p) [ABCD1234].mkv",
+                "CR-ARCHIVE|720p",
+                "700M",
+            ),
+            Entry::for_tests(
+                "[Erai-raws] Frieren - 04v2 [1080p HEVC].mkv",
+                "Ginpachi-Sensei",
+                "500M",
+            ),
+            Entry::for_tests(
+                "[Judas] Re:Zero S2 - 03 [1080p][HEVC x265 10bit].mkv",
+                "ARUTHA-BATCH|1080p",
+                "3G",
+            ),
+        ];
+        let cases = [
+            ("", "1111"),
+            ("frieren", "1110"),
+            ("FRIEREN 1080p", "1010"),
+            ("\"frieren - 05\"", "1100"),
+            ("-720p", "1011"),
+            ("subsplease|erai-raws", "1110"),
+            ("/v\\d/", "0010"),
+            ("/- 0[45]/", "1110"),
+            ("group:SubsPlease", "1100"),
+            ("group:\"Erai-raws\"", "0010"),
+            ("res:1080", "1011"),
+            ("codec:hevc", "0011"),
+            ("bot:\"CR-HOLLAND|NEW\"", "1000"),
+            ("source:nibl", "1111"),
+            ("size:<1G", "0110"),
+            ("size:>=1.4G", "1001"),
+            ("ep:>=5", "1100"),
+            ("ep:4", "0010"),
+            ("Re:Zero", "0001"),
+            ("-group:SubsPlease -res:720p", "0011"),
+            ("- frieren", "1110"),
+        ];
+        for (filter, expected) in cases {
+            let parsed: Filter = filter.parse().unwrap();
+            let matched: String = entries
+                .iter()
+                .map(|entry| if parsed.matches(entry) { '1' } else { '0' })
+                .collect();
+            assert_eq!(matched, expected, "{}", filter);
+        }
+    }
+
+    #[test]
+    fn feed_filters() {
+        let entry = Entry::for_tests("[SubsPlease] Frieren - 05 (1080p).mkv", "Bot", "1.4G");
+        let feed =
+            |resolution: Option<&str>, group: Option<&str>, filter: Option<&str>| FeedFilter {
+                resolution: resolution.map(Into::into),
+                group: group.map(Into::into),
+                filter: filter.map(|f| f.parse().unwrap()),
+            };
+
+        assert!(!feed(None, None, None).narrows());
+        assert!(!feed(None, None, Some("  ")).narrows());
+        assert!(feed(Some("1080p"), None, None).narrows());
+        assert!(feed(None, Some("SubsPlease"), None).narrows());
+        assert!(feed(None, None, Some("frieren")).narrows());
+
+        assert!(feed(None, None, None).matches(&entry));
+        assert!(feed(Some("1080P"), Some("subsplease"), Some("frieren")).matches(&entry));
+        assert!(!feed(Some("1080"), None, None).matches(&entry));
+        assert!(!feed(None, Some("Erai-raws"), None).matches(&entry));
+        assert!(!feed(None, None, Some("-frieren")).matches(&entry));
+    }
+
+    #[test]
+    fn invalid_filters() {
+        for filter in ["/unterminated", "/(/", "size:<big", "ep:>five"] {
+            assert!(filter.parse::<Filter>().is_err(), "{}", filter);
+        }
+    }
+}
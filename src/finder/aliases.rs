@@ -0,0 +1,459 @@
+//! Other names a show goes by, so that searching for "Frieren" also finds
+//! releases named "Sousou no Frieren".
+//!
+//! Aliases come from a list bundled with mahou, an optional `aliases.txt` in
+//! mahou's config directory (in the same format), and optionally AniList.
+
+use super::{Error, FindResult, Finder, Query, Result};
+use crate::cache::Cache;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::{collections::HashSet, fs, io, path::PathBuf, time::Duration};
+
+pub const ANILIST_URL: &str = "https://graphql.anilist.co";
+
+/// How long to wait for AniList before giving up on aliases
+pub const ANILIST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A show's names don't change much
+pub const ANILIST_TTL: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+
+const ANILIST_QUERY: &str = "query ($search: String) {
+    Media(search: $search, type: ANIME) {
+        title { romaji english }
+        synonyms
+    }
+}";
+
+lazy_static! {
+    static ref BUNDLED: Aliases = include_str!("aliases.txt").parse().unwrap();
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Aliases {
+    /// Every name of each show
+    shows: Vec<Vec<String>>,
+}
+
+impl Aliases {
+    /// The aliases bundled with mahou
+    pub fn bundled() -> Self {
+        BUNDLED.clone()
+    }
+
+    /// Where the user's aliases are, if there's a config directory on this
+    /// system
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("mahou").join("aliases.txt"))
+    }
+
+    /// The bundled aliases, plus the user's
+    pub fn load() -> std::result::Result<Self, String> {
+        let mut aliases = Self::bundled();
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(aliases),
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let user: Self = contents.parse()?;
+                user.shows.into_iter().for_each(|names| aliases.add(names));
+                Ok(aliases)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(aliases),
+            Err(e) => Err(format!("Couldn't read {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Adds names of the same show. They're merged with the show's other
+    /// names, if it already had some.
+    pub fn add(&mut self, names: Vec<String>) {
+        let keys: HashSet<_> = names.iter().map(|name| normalize(name)).collect();
+        match self
+            .shows
+            .iter_mut()
+            .find(|show| show.iter().any(|name| keys.contains(&normalize(name))))
+        {
+            Some(show) => {
+                for name in names {
+                    if !show.iter().any(|n| normalize(n) == normalize(&name)) {
+                        show.push(name);
+                    }
+                }
+            }
+            None => self.shows.push(names),
+        }
+    }
+
+    /// `search`, followed by the other names of the show
+    pub fn expand(&self, search: &str) -> Vec<String> {
+        let key = normalize(search);
+        let mut names = vec![search.to_owned()];
+        if let Some(show) = self
+            .shows
+            .iter()
+            .find(|show| show.iter().any(|name| normalize(name) == key))
+        {
+            names.extend(
+                show.iter()
+                    .filter(|name| normalize(name) != key)
+                    .map(|name| searchable(name)),
+            );
+        }
+        names
+    }
+}
+
+impl std::str::FromStr for Aliases {
+    type Err = String;
+
+    /// One show per line, with names separated by `|`. Lines starting with
+    /// `#` are comments.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut aliases = Self::default();
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let names: Vec<String> = line
+                .split('|')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect();
+            aliases.add(names);
+        }
+        Ok(aliases)
+    }
+}
+
+/// Looks up the names of a show on AniList, or any API that speaks the same
+/// GraphQL
+pub struct AniList {
+    client: reqwest::blocking::Client,
+    url: String,
+    cache: Option<Cache>,
+    refresh: bool,
+}
+
+impl Default for AniList {
+    fn default() -> Self {
+        Self {
+            client: reqwest::blocking::Client::builder()
+                .use_rustls_tls()
+                .timeout(ANILIST_TIMEOUT)
+                .connect_timeout(ANILIST_TIMEOUT)
+                .build()
+                .unwrap(),
+            url: ANILIST_URL.into(),
+            cache: None,
+            refresh: false,
+        }
+    }
+}
+
+impl AniList {
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    /// Caches names in `cache`, keyed by the API's URL as well as the
+    /// search. Nothing is cached by default.
+    pub fn with_cache(mut self, cache: Option<Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Asks AniList again, even if the names are cached
+    pub fn with_refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Limits how much disk space cached names can take
+    pub fn with_cache_size(mut self, max_size: u64) -> Self {
+        self.cache = self.cache.map(|cache| cache.with_max_size(max_size));
+        self
+    }
+
+    /// Every name of the show AniList finds for `search`
+    pub fn names(&self, search: &str) -> Result<Vec<String>> {
+        let key = format!("{} {}", self.url, normalize(search));
+        let cached = match self.refresh {
+            true => None,
+            false => self.cache.as_ref().and_then(|c| c.get(&key, ANILIST_TTL)),
+        };
+        let is_cached = cached.is_some();
+        let body = match cached {
+            Some(body) => body,
+            None => {
+                let request = serde_json::json!({
+                    "query": ANILIST_QUERY,
+                    "variables": { "search": search },
+                });
+                let response = self.client.post(&self.url).json(&request).send()?;
+                let status = response.status();
+                // AniList answers 404 when it doesn't know the show, with the
+                // reason in the body like any other error
+                if !status.is_success() && status != reqwest::StatusCode::NOT_FOUND {
+                    return Err(Error::APIError {
+                        api: "anilist",
+                        message: format!("HTTP {}", status),
+                    });
+                }
+                response.text()?
+            }
+        };
+
+        let response: AniListResponse = serde_json::from_str(&body)?;
+        match response.errors.into_iter().next() {
+            // Not finding the show isn't an error, it just has no aliases
+            Some(error) if error.status == Some(404) => return Ok(Vec::new()),
+            Some(error) => {
+                return Err(Error::APIError {
+                    api: "anilist",
+                    message: error.message,
+                })
+            }
+            None if !is_cached => {
+                if let Some(cache) = &self.cache {
+                    cache.put(&key, &body);
+                }
+            }
+            None => {}
+        }
+        let media = match response.data.and_then(|data| data.media) {
+            Some(media) => media,
+            None => return Ok(Vec::new()),
+        };
+
+        // Names in other scripts won't be in any release name
+        Ok([media.title.romaji, media.title.english]
+            .into_iter()
+            .flatten()
+            .chain(media.synonyms)
+            .filter(|name| name.is_ascii())
+            .collect())
+    }
+}
+
+#[derive(Deserialize)]
+struct AniListResponse {
+    data: Option<AniListData>,
+    #[serde(default)]
+    errors: Vec<AniListError>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AniListData {
+    media: Option<Media>,
+}
+
+#[derive(Deserialize)]
+struct Media {
+    title: MediaTitle,
+    #[serde(default)]
+    synonyms: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct MediaTitle {
+    romaji: Option<String>,
+    english: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AniListError {
+    message: String,
+    status: Option<u16>,
+}
+
+/// Searches for every name of a show, and merges what's found
+pub struct Aliased<F> {
+    finder: F,
+    aliases: Aliases,
+    anilist: Option<AniList>,
+}
+
+impl<F: Finder> Aliased<F> {
+    pub fn new(finder: F, aliases: Aliases) -> Self {
+        Self {
+            finder,
+            aliases,
+            anilist: None,
+        }
+    }
+
+    /// Also asks AniList for the show's names
+    pub fn with_anilist(mut self, anilist: Option<AniList>) -> Self {
+        self.anilist = anilist;
+        self
+    }
+}
+
+impl<F: Finder> Finder for Aliased<F> {
+    /// Failing to search for an alias only produces a warning, but failing to
+    /// search for the name that was asked for is an error
+    fn find(&self, query: &Query) -> Result<FindResult> {
+        let mut warnings = Vec::new();
+        let mut aliases = self.aliases.clone();
+        if let Some(anilist) = &self.anilist {
+            match anilist.names(&query.search) {
+                Ok(names) if !names.is_empty() => {
+                    aliases.add(names.into_iter().chain([query.search.clone()]).collect())
+                }
+                Ok(_) => {}
+                Err(e) => warnings.push(format!("Couldn't look up aliases on AniList: {}", e)),
+            }
+        }
+
+        let mut names = aliases.expand(&query.search);
+        let mut seen_names = HashSet::new();
+        names.retain(|name| seen_names.insert(normalize(name)));
+
+        // A season mapping written for any of the names applies to all of them
+        let query = &Query {
+            seasons: query.seasons.with_aliases(&names),
+            ..query.clone()
+        };
+        let mut merged = self.finder.find(query)?;
+        merged.warnings.append(&mut warnings);
+        let mut seen: HashSet<_> = merged
+            .entries
+            .iter()
+            .map(|entry| (entry.name.to_lowercase(), entry.size.to_lowercase()))
+            .collect();
+
+        for name in names.into_iter().skip(1) {
+            let alias = Query {
+                search: name.clone(),
+                ..query.clone()
+            };
+            let result = match self.finder.find(&alias) {
+                Ok(result) => result,
+                Err(e) => {
+                    merged
+                        .warnings
+                        .push(format!("Couldn't search for {}: {}", name, e));
+                    continue;
+                }
+            };
+
+            merged.notes.push(format!("also searched for {}", name));
+            merged.warnings.extend(result.warnings);
+            merged.notes.extend(result.notes);
+            for entry in result.entries {
+                if seen.insert((entry.name.to_lowercase(), entry.size.to_lowercase())) {
+                    merged.entries.push(entry);
+                }
+            }
+        }
+
+        Ok(merged)
+    }
+}
+
+/// Names are compared case-insensitively, ignoring punctuation
+fn normalize(name: &str) -> String {
+    searchable(name).to_lowercase()
+}
+
+/// Punctuation like in "Frieren: Beyond Journey's End" is rarely in release
+/// names, so it's left out of searches
+fn searchable(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric() && c != '\'' && c != '-')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::finder::{EpisodeNumber, StandIn};
+
+    fn frieren() -> Aliases {
+        "Frieren | Sousou no Frieren".parse().unwrap()
+    }
+
+    fn frieren_query() -> Query {
+        Query::new("Frieren".into(), None, EpisodeNumber::All)
+    }
+
+    #[test]
+    fn bundled_names_are_long_enough_to_search_for() {
+        for name in Aliases::bundled().shows.iter().flatten() {
+            assert!(name.len() > 4, "{} is too short", name);
+        }
+    }
+
+    #[test]
+    fn bundled_shows_have_other_names() {
+        for names in &Aliases::bundled().shows {
+            assert!(names.len() > 1, "{} has no other names", names[0]);
+        }
+    }
+
+    #[test]
+    fn merges_what_every_name_finds() {
+        let finder = StandIn(|search| match search {
+            "Frieren" => Ok((
+                vec!["[A] Frieren - 01.mkv", "[B] Frieren - 01.mkv"],
+                vec!["one bot is slow".into()],
+            )),
+            "Sousou no Frieren" => Ok((
+                vec!["[b] frieren - 01.MKV", "[C] Sousou no Frieren - 01.mkv"],
+                vec!["another bot is slow".into()],
+            )),
+            _ => Ok((Vec::new(), Vec::new())),
+        });
+        let result = Aliased::new(finder, frieren())
+            .find(&frieren_query())
+            .unwrap();
+
+        let names: Vec<_> = result.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "[A] Frieren - 01.mkv",
+                "[B] Frieren - 01.mkv",
+                "[C] Sousou no Frieren - 01.mkv"
+            ]
+        );
+        assert_eq!(result.warnings, ["one bot is slow", "another bot is slow"]);
+        assert_eq!(result.notes, ["also searched for Sousou no Frieren"]);
+    }
+
+    #[test]
+    fn only_failing_to_search_for_the_name_itself_is_an_error() {
+        let finder = StandIn(|search| match search {
+            "Frieren" => Ok((vec!["[A] Frieren - 01.mkv"], Vec::new())),
+            _ => Err(Error::Config("down".into())),
+        });
+        let result = Aliased::new(finder, frieren())
+            .find(&frieren_query())
+            .unwrap();
+        assert_eq!(result.entries.len(), 1);
+        assert_eq!(
+            result.warnings,
+            ["Couldn't search for Sousou no Frieren: down"]
+        );
+
+        let finder = StandIn(|_| Err(Error::Config("down".into())));
+        assert!(Aliased::new(finder, frieren())
+            .find(&frieren_query())
+            .is_err());
+    }
+
+    #[test]
+    fn expands_every_name() {
+        let aliases = Aliases::bundled();
+        assert_eq!(
+            aliases.expand("attack on titan"),
+            ["attack on titan", "Shingeki no Kyojin"]
+        );
+        assert_eq!(aliases.expand("Some Show"), ["Some Show"]);
+    }
+}
@@ -0,0 +1,121 @@
+//! New packs, as bots announce them in their channel with lines like
+//! `** Added #1234 [1.4G] [SubsPlease] Show - 01 (1080p).mkv`, so that new
+//! releases can be downloaded within seconds instead of on the next search.
+
+use super::{iroffer, Entry};
+use crate::{downloader::irc, release::ReleaseInfo};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::sync::Arc;
+
+lazy_static! {
+    /// "Added" or "New", then the pack number, size and name
+    static ref ANNOUNCEMENT_REGEX: Regex =
+        Regex::new(r"(?i)\b(?:added|new)\b[^#]*#(\d+)\s*\[\s*([^\]]+?)\s*\]\s+(.+?)\s*$").unwrap();
+}
+
+/// The entry a bot announced in `message`, a raw line from `server`. Messages
+/// that aren't announcements in a channel are `None`, and so are the ones sent
+/// by nicks that `is_bot` doesn't know, since anyone in the channel could
+/// announce a pack of their own.
+pub fn parse(server: &str, message: &str, is_bot: impl FnOnce(&str) -> bool) -> Option<Entry> {
+    let captures = irc::MESSAGE_REGEX.captures(message)?;
+    let (bot, channel) = (&captures[1], &captures[3]);
+    if !channel.starts_with('#') || !is_bot(bot) {
+        return None;
+    }
+
+    let text = iroffer::strip_formatting(&captures[4]);
+    let announcement = ANNOUNCEMENT_REGEX.captures(&text)?;
+    let size = announcement[2].to_owned();
+    // Anything else that happens to say "new" and "#"
+    let size_bytes = Some(super::parse_size(&size)?);
+    let name = announcement[3].to_owned();
+
+    Some(Entry {
+        package_number: announcement[1].parse().ok()?,
+        bot_id: 0,
+        bot_name: bot.to_owned(),
+        episode: ReleaseInfo::parse(&name).episode,
+        name,
+        size,
+        size_bytes,
+        uploaded: Some(chrono::Utc::now().naive_utc()),
+        source: "announcement".into(),
+        network: Arc::new(irc::Config {
+            server: server.to_owned(),
+            channel: channel.to_owned(),
+            nickname: irc::random_nickname(),
+        }),
+        extras: Default::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: &str = "irc.rizon.net:6667";
+
+    fn known_bot(nick: &str) -> bool {
+        ["CR-HOLLAND|NEW", "Ginpachi-Sensei", "Bot"].contains(&nick)
+    }
+
+    #[test]
+    fn announcements() {
+        let entry = parse(
+            SERVER,
+            ":CR-HOLLAND|NEW!~bot@host PRIVMSG #nibl :\x02\x0303** Added\x03\x02 \x0307#1234\x03 [\x02 1.4G \x02] [SubsPlease] Show - 01 (1080p) [F02B9CEE].mkv\r\n",
+            known_bot,
+        )
+        .unwrap();
+        assert_eq!(entry.bot_name, "CR-HOLLAND|NEW");
+        assert_eq!(entry.package_number, 1234);
+        assert_eq!(entry.size, "1.4G");
+        assert_eq!(entry.size_bytes, super::super::parse_size("1.4G"));
+        assert_eq!(entry.name, "[SubsPlease] Show - 01 (1080p) [F02B9CEE].mkv");
+        assert_eq!(entry.episode, Some(1));
+        assert_eq!(entry.network.server, SERVER);
+        assert_eq!(entry.network.channel, "#nibl");
+        // Uploads are in UTC, like Nibl's
+        let age = chrono::Utc::now().naive_utc() - entry.uploaded.unwrap();
+        assert!(age.num_seconds().abs() < 60);
+
+        let entry = parse(
+            SERVER,
+            ":Ginpachi-Sensei!~b@host PRIVMSG #Nibl :New pack #77 [350M] Show - 12.mkv\n",
+            known_bot,
+        )
+        .unwrap();
+        assert_eq!(entry.package_number, 77);
+        assert_eq!(entry.name, "Show - 12.mkv");
+        assert_eq!(entry.network.channel, "#Nibl");
+    }
+
+    #[test]
+    fn not_announcements() {
+        for message in [
+            // Not to a channel
+            ":Bot!~b@host PRIVMSG real-person-1 :** Added #1 [1.4G] Show - 01.mkv",
+            ":Bot!~b@host NOTICE real-person-1 :** Added #1 [1.4G] Show - 01.mkv",
+            // "New" or "#" without a size
+            ":someone!~u@host PRIVMSG #nibl :new episode is out, check #nibl",
+            ":someone!~u@host PRIVMSG #nibl :Added #3 to my list [soon] Show - 01.mkv",
+            ":someone!~u@host PRIVMSG #nibl :anything new in #1?",
+            // Not a message at all
+            ":irc.rizon.net 332 me #nibl :** Added #1 [1.4G] Show - 01.mkv",
+            "PING :irc.rizon.net",
+        ] {
+            assert!(parse(SERVER, message, |_| true).is_none(), "{:?}", message);
+        }
+    }
+
+    #[test]
+    fn only_from_known_bots() {
+        let message =
+            ":someone!~u@host PRIVMSG #nibl :** Added #1 [1.4G] [SubsPlease] Show - 07 (1080p).mkv";
+        assert!(parse(SERVER, message, known_bot).is_none());
+        let entry = parse(SERVER, message, |nick| nick == "someone").unwrap();
+        assert_eq!(entry.bot_name, "someone");
+    }
+}
@@ -0,0 +1,350 @@
+//! Parsing for iroffer's pack lists, which look like
+//! `#12  5x [1.4G] [Group] Show - 01 (1080p).mkv` in text form, and can also
+//! be published as XML or HTML.
+
+use super::{Entry, EpisodeNumber, Latest, Query, ReleaseKey, Upload};
+use crate::{downloader::irc, release::ReleaseInfo};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::sync::Arc;
+
+lazy_static! {
+    static ref PACK_REGEX: Regex =
+        Regex::new(r"^\s*#(\d+)\s+(\d+)x\s+\[\s*([^\]]*?)\s*\]\s+(.+?)\s*$").unwrap();
+    static ref FORMATTING_REGEX: Regex =
+        Regex::new(r"\x03(?:\d{1,2}(?:,\d{1,2})?)?|[\x02\x0f\x16\x1d\x1f]").unwrap();
+    static ref XML_PACK_REGEX: Regex = Regex::new(r"(?s)<pack>(.*?)</pack>").unwrap();
+    static ref XML_FIELD_REGEX: Regex = Regex::new(
+        r"(?s)<(packnr|packname|packsize|packgets)>\s*(?:<!\[CDATA\[(.*?)\]\]>|([^<]*))\s*</"
+    )
+    .unwrap();
+    static ref HTML_BREAK_REGEX: Regex =
+        Regex::new(r"(?i)<br\s*/?>|</(?:p|div|tr|li|pre)>").unwrap();
+    static ref HTML_CELL_REGEX: Regex = Regex::new(r"(?i)</t[dh]>").unwrap();
+    static ref HTML_TAG_REGEX: Regex = Regex::new(r"(?s)<[^>]*>").unwrap();
+    static ref HTML_HINT_REGEX: Regex =
+        Regex::new(r"(?i)<(?:!doctype|html|body|pre|br|table|div|p)\b").unwrap();
+}
+
+/// A pack offered by a bot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pack {
+    pub number: i32,
+    pub gets: u32,
+    pub size: String,
+    pub name: String,
+}
+
+/// Removes IRC bold/color/underline codes from a line
+pub fn strip_formatting(line: &str) -> String {
+    FORMATTING_REGEX.replace_all(line, "").into_owned()
+}
+
+pub fn parse_line(line: &str) -> Option<Pack> {
+    let line = strip_formatting(line);
+    let captures = PACK_REGEX.captures(&line)?;
+    Some(Pack {
+        number: captures[1].parse().ok()?,
+        gets: captures[2].parse().ok()?,
+        size: captures[3].to_owned(),
+        name: captures[4].to_owned(),
+    })
+}
+
+/// Parses every pack in a list, skipping headers and other chatter
+pub fn parse_text(text: &str) -> Vec<Pack> {
+    text.lines().filter_map(parse_line).collect()
+}
+
+/// Parses iroffer's XML pack list format
+pub fn parse_xml(xml: &str) -> Vec<Pack> {
+    XML_PACK_REGEX
+        .captures_iter(xml)
+        .filter_map(|pack| {
+            let mut number = None;
+            let mut gets = 0;
+            let mut size = String::new();
+            let mut name = None;
+            for field in XML_FIELD_REGEX.captures_iter(&pack[1]) {
+                let value = field.get(2).or(field.get(3)).map_or("", |m| m.as_str());
+                let value = decode_entities(value.trim());
+                match &field[1] {
+                    "packnr" => number = value.parse().ok(),
+                    "packname" => name = Some(value),
+                    "packsize" => size = value,
+                    "packgets" => gets = value.parse().unwrap_or(0),
+                    _ => {}
+                }
+            }
+            Some(Pack {
+                number: number?,
+                gets,
+                size,
+                name: name?,
+            })
+        })
+        .collect()
+}
+
+/// Parses an HTML page containing a text pack list or a table with a pack on
+/// each row, which is how most bots' websites show them
+pub fn parse_html(html: &str) -> Vec<Pack> {
+    let text = HTML_BREAK_REGEX.replace_all(html, "\n");
+    let text = HTML_CELL_REGEX.replace_all(&text, " ");
+    let text = HTML_TAG_REGEX.replace_all(&text, "");
+    parse_text(&decode_entities(&text))
+}
+
+/// Parses a pack list in any of the formats iroffer publishes
+pub fn parse_any(list: &str) -> Vec<Pack> {
+    if list.contains("<packlist") {
+        parse_xml(list)
+    } else if HTML_HINT_REGEX.is_match(list) {
+        parse_html(list)
+    } else {
+        parse_text(list)
+    }
+}
+
+fn decode_entities(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Turns a bot's packs into the entries that match `query`, along with notes
+/// on how they were picked
+pub fn search(
+    query: &Query,
+    source: &str,
+    address: &irc::BotAddress,
+    packs: Vec<Pack>,
+) -> (Vec<Entry>, Vec<String>) {
+    let network = Arc::new(address.config.clone());
+    let packs: Vec<(Pack, Option<i32>, Option<Upload>)> = packs
+        .into_iter()
+        .filter(|p| query.matches_name(&p.name))
+        .map(|p| {
+            let release = ReleaseInfo::parse(&p.name);
+            let upload = release.episode.map(|episode| Upload {
+                key: ReleaseKey::of(&release),
+                position: query.position(&release, episode),
+                uploaded: None,
+            });
+            (p, release.episode, upload)
+        })
+        .collect();
+
+    let uploads: Vec<Upload> = packs.iter().filter_map(|(_, _, u)| u.clone()).collect();
+    let latest = Latest::new(&uploads);
+
+    let entries = packs
+        .into_iter()
+        .filter(|(_, _, upload)| match upload {
+            Some(upload) => {
+                query.wants_group(&upload.key)
+                    && query
+                        .episode
+                        .selects(&upload.position, latest.of(&upload.key, query))
+            }
+            None => query.episode == EpisodeNumber::All && query.group.is_none(),
+        })
+        .map(|(p, episode, _)| Entry {
+            package_number: p.number,
+            bot_id: 0,
+            bot_name: address.bot.clone(),
+            name: p.name,
+            size_bytes: super::parse_size(&p.size),
+            size: p.size,
+            uploaded: None,
+            episode,
+            source: source.to_owned(),
+            network: network.clone(),
+            extras: [("gets".to_owned(), p.gets.to_string())].into(),
+        })
+        .collect();
+    (entries, latest.notes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pack(number: i32, gets: u32, size: &str, name: &str) -> Pack {
+        Pack {
+            number,
+            gets,
+            size: size.to_owned(),
+            name: name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn pack_lines() {
+        let table = [
+            (
+                "#12  5x [1.4G] [Group] Show - 01 (1080p).mkv",
+                Some(pack(12, 5, "1.4G", "[Group] Show - 01 (1080p).mkv")),
+            ),
+            (
+                "  #1   0x [ 350M] Show - 02.mkv  ",
+                Some(pack(1, 0, "350M", "Show - 02.mkv")),
+            ),
+            (
+                "\x0304#3\x03  \x0312,01120x\x03 [\x02700M\x02] \x1f[G] Show - 03.mkv\x0f",
+                Some(pack(3, 120, "700M", "[G] Show - 03.mkv")),
+            ),
+            ("** 12 packs **  2 of 5 slots open, Record: 110.3kB/s", None),
+            (
+                "** Bandwidth Usage ** Current: 0.0kB/s, Record: 2.1MB/s",
+                None,
+            ),
+            (
+                "** To request a file, type \"/msg Bot xdcc send #x\" **",
+                None,
+            ),
+            ("Total Offered: 1.2 TB  Total Transferred: 3.4 TB", None),
+            ("#12 [1.4G] no gets", None),
+            ("", None),
+        ];
+        for (line, expected) in table {
+            assert_eq!(parse_line(line), expected, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn text_lists() {
+        let list = "\
+** 2 packs **  1 of 2 slots open
+** To request a file, type \"/msg Bot xdcc send #x\" **
+#1  5x [1.4G] [G] Show - 01 (1080p).mkv
+#2  3x [1.4G] [G] Show - 02 (1080p).mkv
+Total Offered: 2.8 GB  Total Transferred: 11.2 GB
+";
+        assert_eq!(
+            parse_text(list),
+            vec![
+                pack(1, 5, "1.4G", "[G] Show - 01 (1080p).mkv"),
+                pack(2, 3, "1.4G", "[G] Show - 02 (1080p).mkv"),
+            ]
+        );
+    }
+
+    #[test]
+    fn searches_episodes() {
+        let address: irc::BotAddress = "Bot@irc.example.net/#chan".parse().unwrap();
+        let packs = parse_text(
+            "\
+#1  5x [1.4G] [G] Show - 01 (1080p).mkv
+#2  3x [1.4G] [G] Show - 02 (1080p).mkv
+#3  1x [350M] [G] Show - 02 (480p).mkv
+#4  9x [1.4G] [G] Other Show - 07 (1080p).mkv
+#5  2x [20G] [G] Show Soundtrack.zip
+#6  8x [1.4G] [G] Chaotic - 05 (1080p).mkv
+",
+        );
+        let numbers = |episode: EpisodeNumber, resolution: Option<&str>| {
+            let query = Query::new("Show".into(), resolution.map(Into::into), episode);
+            let (entries, _) = search(&query, "packlist", &address, packs.clone());
+            entries.iter().map(|e| e.package_number).collect::<Vec<_>>()
+        };
+
+        assert_eq!(numbers(EpisodeNumber::All, None), [1, 2, 3, 4, 5]);
+        assert_eq!(numbers(EpisodeNumber::Number(2), None), [2, 3]);
+        assert_eq!(numbers(EpisodeNumber::Number(2), Some("1080p")), [2]);
+        // The latest of each show
+        assert_eq!(numbers(EpisodeNumber::Latest, None), [2, 3, 4]);
+
+        let (entries, _) = search(
+            &Query::new("Show".into(), None, EpisodeNumber::Number(1)),
+            "packlist",
+            &address,
+            packs.clone(),
+        );
+        assert_eq!(entries[0].bot_name, "Bot");
+        assert_eq!(entries[0].episode, Some(1));
+        assert_eq!(entries[0].size_bytes, super::super::parse_size("1.4G"));
+        assert_eq!(entries[0].network.channel, "#chan");
+        assert_eq!(entries[0].extras["gets"], "5");
+    }
+
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE packlist SYSTEM "iroffer.dtd">
+<packlist>
+<pack>
+  <packnr>1</packnr>
+  <packname><![CDATA[[G] Show & Friends - 01 (1080p).mkv]]></packname>
+  <packsize><![CDATA[1.4G]]></packsize>
+  <packbytes>1503238553</packbytes>
+  <packgets>5</packgets>
+  <adddate>1682944496</adddate>
+</pack>
+<pack>
+  <packnr>2</packnr>
+  <packname>[G] Tom &amp; Jerry &lt;Uncut&gt; - 02.mkv</packname>
+  <packsize>350M</packsize>
+  <packgets>0</packgets>
+</pack>
+<pack>
+  <packname>No number.mkv</packname>
+</pack>
+<sysinfo>
+  <slots><slotsfree>2</slotsfree><slotsmax>5</slotsmax></slots>
+</sysinfo>
+</packlist>
+"#;
+
+    const HTML: &str = r#"<!DOCTYPE html>
+<html><head><title>Bot's packs</title></head>
+<body>
+<h1>Bot</h1>
+<table>
+<tr><th>Pack</th><th>Gets</th><th>Size</th><th>Name</th></tr>
+<tr><td>#1</td><td>5x</td><td>[1.4G]</td><td><a href="/1">[G] Show &amp; Friends - 01 (1080p).mkv</a></td></tr>
+<tr><td>#2</td><td>0x</td><td>[350M]</td><td>[G]&nbsp;Show - 02.mkv</td></tr>
+</table>
+<p>Total Offered: 1.7 GB</p>
+</body></html>
+"#;
+
+    #[test]
+    fn xml_lists() {
+        let expected = vec![
+            pack(1, 5, "1.4G", "[G] Show & Friends - 01 (1080p).mkv"),
+            pack(2, 0, "350M", "[G] Tom & Jerry <Uncut> - 02.mkv"),
+        ];
+        assert_eq!(parse_xml(XML), expected);
+        assert_eq!(parse_any(XML), expected);
+    }
+
+    #[test]
+    fn html_lists() {
+        let expected = vec![
+            pack(1, 5, "1.4G", "[G] Show & Friends - 01 (1080p).mkv"),
+            pack(2, 0, "350M", "[G] Show - 02.mkv"),
+        ];
+        assert_eq!(parse_html(HTML), expected);
+        assert_eq!(parse_any(HTML), expected);
+
+        let pre = "<html><body><pre>\n#1  5x [1.4G] [G] Show - 01.mkv<br>\n</pre></body></html>";
+        assert_eq!(
+            parse_any(pre),
+            vec![pack(1, 5, "1.4G", "[G] Show - 01.mkv")]
+        );
+    }
+
+    #[test]
+    fn detects_formats() {
+        // Text that merely mentions tags stays text
+        let text = "#1  5x [1.4G] [G] <Show> - 01.mkv\n";
+        assert_eq!(
+            parse_any(text),
+            vec![pack(1, 5, "1.4G", "[G] <Show> - 01.mkv")]
+        );
+        assert_eq!(parse_any(XML).len(), 2);
+        assert_eq!(parse_any(HTML).len(), 2);
+    }
+}
@@ -0,0 +1,258 @@
+//! Working out which episode is the latest. It's decided separately for each
+//! show, and only among its recent uploads, so that a re-upload of an old
+//! episode or another show matching the search don't count. Searches for one
+//! group's releases get that group's latest instead.
+
+use super::{EpisodePosition, Query};
+use crate::release::ReleaseInfo;
+use chrono::{Duration, NaiveDateTime};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+/// Uploads this many days older than the newest one of their release aren't
+/// considered when looking for the latest episode
+pub const RECENT_DAYS: i64 = 14;
+
+/// The latest episode of a release, overall and in each season
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LatestEpisodes {
+    pub absolute: i32,
+    pub seasons: HashMap<u32, i32>,
+}
+
+impl LatestEpisodes {
+    /// The highest episodes in `positions`
+    pub fn highest<'p>(positions: impl IntoIterator<Item = &'p EpisodePosition>) -> Self {
+        let mut absolute = None;
+        let mut seasons = HashMap::new();
+        for position in positions {
+            absolute = absolute.max(Some(position.absolute));
+            let latest = seasons.entry(position.season).or_insert(position.episode);
+            *latest = position.episode.max(*latest);
+        }
+        Self {
+            absolute: absolute.unwrap_or(1),
+            seasons,
+        }
+    }
+
+    pub fn in_season(&self, season: u32) -> i32 {
+        self.seasons.get(&season).copied().unwrap_or(1)
+    }
+}
+
+/// Releases with the same key are different episodes of the same thing
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ReleaseKey {
+    pub show: String,
+    pub group: Option<String>,
+    pub resolution: Option<String>,
+}
+
+impl ReleaseKey {
+    pub fn of(release: &ReleaseInfo) -> Self {
+        Self {
+            show: release.title.as_deref().unwrap_or("").to_lowercase(),
+            group: release.group.as_deref().map(str::to_lowercase),
+            resolution: release.resolution.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ReleaseKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.show)?;
+        if let Some(group) = &self.group {
+            write!(f, " [{}]", group)?;
+        }
+        if let Some(resolution) = &self.resolution {
+            write!(f, " {}", resolution)?;
+        }
+        Ok(())
+    }
+}
+
+/// A release found by a source, as far as telling the latest episode goes
+#[derive(Debug, Clone)]
+pub struct Upload {
+    pub key: ReleaseKey,
+    pub position: EpisodePosition,
+    /// When the source got it, if it knows
+    pub uploaded: Option<NaiveDateTime>,
+}
+
+/// The latest episode of every show a source found
+#[derive(Debug, Default, Clone)]
+pub struct Latest {
+    shows: HashMap<String, LatestEpisodes>,
+    releases: HashMap<ReleaseKey, LatestEpisodes>,
+    fallback: LatestEpisodes,
+    /// Why each episode was considered the latest
+    pub notes: Vec<String>,
+}
+
+impl Latest {
+    /// Picks the highest episode of each show among the recent uploads of
+    /// all its groups and resolutions, so that a group that's behind doesn't
+    /// make its older episode the latest. Uploads without a timestamp are
+    /// always considered recent.
+    pub fn new(uploads: &[Upload]) -> Self {
+        let mut by_show: BTreeMap<&str, BTreeMap<&ReleaseKey, Vec<&Upload>>> = BTreeMap::new();
+        for upload in uploads {
+            by_show
+                .entry(&upload.key.show)
+                .or_default()
+                .entry(&upload.key)
+                .or_default()
+                .push(upload);
+        }
+
+        let mut latest = Self::default();
+        for (show, releases) in by_show {
+            let newest = releases.values().flatten().filter_map(|u| u.uploaded).max();
+            let cutoff = newest.map(|newest| newest - Duration::days(RECENT_DAYS));
+            let is_old = |u: &&Upload| matches!((u.uploaded, cutoff), (Some(t), Some(c)) if t < c);
+            let recent: HashMap<&ReleaseKey, Vec<&Upload>> = releases
+                .iter()
+                .map(|(&key, uploads)| {
+                    (
+                        key,
+                        uploads.iter().copied().filter(|u| !is_old(u)).collect(),
+                    )
+                })
+                .collect();
+            let episodes = LatestEpisodes::highest(recent.values().flatten().map(|u| &u.position));
+
+            let mut note = format!("latest of {} is episode {}", show, episodes.absolute);
+            if let Some(newest) = newest {
+                note += &format!(", newest upload on {}", newest.format("%Y-%m-%d %H:%M"));
+            }
+            let ignored = releases.values().flatten().count() - recent.values().flatten().count();
+            if ignored > 0 {
+                note += &format!(
+                    ", ignoring {} uploads from more than {} days before that",
+                    ignored, RECENT_DAYS
+                );
+            }
+            latest.notes.push(note);
+
+            for &key in releases.keys() {
+                let uploads = &recent[key];
+                if uploads.is_empty() {
+                    continue;
+                }
+                let own = LatestEpisodes::highest(uploads.iter().map(|u| &u.position));
+                if own.absolute < episodes.absolute {
+                    latest
+                        .notes
+                        .push(format!("{} is behind, at episode {}", key, own.absolute));
+                }
+                latest.releases.insert(key.clone(), own);
+            }
+            latest.shows.insert(show.to_owned(), episodes);
+        }
+        latest
+    }
+
+    /// The latest episodes of the show of the release with `key`. When
+    /// `query` only wants one group's releases, it's the latest of the
+    /// release itself, so that a group that's behind still has one.
+    pub fn of(&self, key: &ReleaseKey, query: &Query) -> &LatestEpisodes {
+        let own = query.group.as_ref().and_then(|_| self.releases.get(key));
+        own.or_else(|| self.shows.get(&key.show))
+            .unwrap_or(&self.fallback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::finder::{EpisodeNumber, Query};
+
+    fn upload(name: &str, uploaded: &str) -> Upload {
+        let release = ReleaseInfo::parse(name);
+        let query = Query::new("Show".into(), None, EpisodeNumber::Latest);
+        Upload {
+            key: ReleaseKey::of(&release),
+            position: query.position(&release, release.episode.unwrap()),
+            uploaded: NaiveDateTime::parse_from_str(uploaded, "%Y-%m-%d %H:%M").ok(),
+        }
+    }
+
+    fn latest_of(latest: &Latest, name: &str) -> i32 {
+        let query = Query::new("Show".into(), None, EpisodeNumber::Latest);
+        latest
+            .of(&ReleaseKey::of(&ReleaseInfo::parse(name)), &query)
+            .absolute
+    }
+
+    #[test]
+    fn old_uploads_dont_count() {
+        let uploads = [
+            // From the show's last run, a year ago
+            upload("[G] Show - 24 (1080p).mkv", "2022-05-01 12:00"),
+            upload("[G] Show - 04 (1080p).mkv", "2023-04-24 12:00"),
+            upload("[G] Show - 05 (1080p).mkv", "2023-05-01 12:00"),
+            // A re-upload of an older episode is recent, but not the latest
+            upload("[G] Show - 03v2 (1080p).mkv", "2023-05-02 12:00"),
+        ];
+        let latest = Latest::new(&uploads);
+        assert_eq!(latest_of(&latest, "[G] Show - 01 (1080p).mkv"), 5);
+        assert!(latest.notes[0].contains("ignoring 1 uploads"));
+    }
+
+    #[test]
+    fn each_show_has_its_own_latest() {
+        let uploads = [
+            upload("[G] Show - 05 (1080p).mkv", "2023-05-01 12:00"),
+            upload("[G] Show - 06 (720p).mkv", "2023-05-08 12:00"),
+            upload("[G] Show Spinoff - 12 (1080p).mkv", "2023-05-08 12:00"),
+            upload("[G] Show - 04 (1080p).mkv", "bad timestamp"),
+        ];
+        let latest = Latest::new(&uploads);
+        assert_eq!(latest_of(&latest, "[G] Show - 01 (1080p).mkv"), 6);
+        assert_eq!(latest_of(&latest, "[G] Show - 01 (720p).mkv"), 6);
+        assert_eq!(latest_of(&latest, "[G] Show Spinoff - 01 (1080p).mkv"), 12);
+        // Never seen
+        let unseen = ReleaseKey::of(&ReleaseInfo::parse("[New] Other - 01 (1080p).mkv"));
+        let query = Query::new("Other".into(), None, EpisodeNumber::Latest);
+        assert_eq!(latest.of(&unseen, &query), &LatestEpisodes::default());
+    }
+
+    #[test]
+    fn groups_behind_dont_have_their_own_latest() {
+        let uploads = [
+            upload("[G] Show - 04 (1080p).mkv", "2023-05-01 12:00"),
+            upload("[G] Show - 05 (1080p).mkv", "2023-05-08 12:00"),
+            upload("[Other] Show - 06 (1080p).mkv", "2023-05-01 12:00"),
+            upload("[Other] Show - 07 (1080p).mkv", "2023-05-08 12:00"),
+            // Dropped the show long ago, and its last episode isn't the latest
+            upload("[Gone] Show - 24 (1080p).mkv", "2022-09-01 12:00"),
+        ];
+        let latest = Latest::new(&uploads);
+        assert_eq!(latest_of(&latest, "[G] Show - 01 (1080p).mkv"), 7);
+        assert_eq!(latest_of(&latest, "[Other] Show - 01 (1080p).mkv"), 7);
+        assert_eq!(latest_of(&latest, "[Gone] Show - 01 (1080p).mkv"), 7);
+        assert!(latest.notes[0].contains("ignoring 1 uploads"));
+        assert!(latest
+            .notes
+            .contains(&"show [g] 1080p is behind, at episode 5".to_owned()));
+    }
+
+    #[test]
+    fn groups_behind_have_their_own_latest_when_asked_for() {
+        let uploads = [
+            upload("[G] Show - 05 (1080p).mkv", "2023-05-08 12:00"),
+            upload("[Other] Show - 07 (1080p).mkv", "2023-05-08 12:00"),
+        ];
+        let latest = Latest::new(&uploads);
+        let query = Query::new("Show".into(), None, EpisodeNumber::Latest).with_group(Some("g"));
+        let g = ReleaseKey::of(&ReleaseInfo::parse("[G] Show - 01 (1080p).mkv"));
+        assert_eq!(latest.of(&g, &query).absolute, 5);
+        // Not uploaded by the group in this resolution, so it's the show's
+        let g_720p = ReleaseKey::of(&ReleaseInfo::parse("[G] Show - 01 (720p).mkv"));
+        assert_eq!(latest.of(&g_720p, &query).absolute, 7);
+    }
+}
@@ -1,9 +1,28 @@
+use crate::{downloader::irc, release::ReleaseInfo, seasons::SeasonMap};
+use chrono::NaiveDateTime;
 use owo_colors::OwoColorize;
-use std::{fmt, result::Result as StdResult};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    result::Result as StdResult,
+    sync::Arc,
+};
 use thiserror::Error;
 
+pub mod aliases;
+pub mod announcements;
+pub mod iroffer;
+pub mod latest;
+pub mod multi;
 pub mod nibl;
-pub use nibl::Nibl;
+pub mod packlist;
+pub mod xdcc;
+pub use aliases::{Aliased, Aliases, AniList};
+pub use latest::{Latest, LatestEpisodes, ReleaseKey, Upload};
+pub use multi::Multi;
+pub use nibl::{Nibl, NiblBuilder, NIBL_CONFIG};
+pub use packlist::{Packlist, PacklistSource};
+pub use xdcc::XdccList;
 
 /////////////////////////////////////////////////
 //                    Error                    //
@@ -12,8 +31,20 @@ pub use nibl::Nibl;
 pub enum Error {
     #[error("Request failed: {0}")]
     Reqwest(#[from] reqwest::Error),
+    #[error("Couldn't parse the response: {0}")]
+    Json(#[from] serde_json::Error),
     #[error("The {api} API returned and error: {message}")]
     APIError { api: &'static str, message: String },
+    #[error("The {api} API is unavailable, gave up after {attempts} attempts: {reason}")]
+    Unavailable {
+        api: &'static str,
+        attempts: u32,
+        reason: String,
+    },
+    #[error("IRC error: {0}")]
+    Irc(#[from] crate::downloader::Error),
+    #[error("{0}")]
+    Config(String),
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -22,8 +53,11 @@ type Result<T> = std::result::Result<T, Error>;
 //                    FindResult                    //
 //////////////////////////////////////////////////////
 pub struct FindResult {
-    pub irc_config: crate::downloader::irc::Config,
     pub entries: Vec<Entry>,
+    /// Problems that didn't stop the search, like a source being down
+    pub warnings: Vec<String>,
+    /// How results were picked, like why an episode is considered the latest
+    pub notes: Vec<String>,
 }
 
 //////////////////////////////////////////////////
@@ -33,28 +67,151 @@ pub trait Finder {
     fn find(&self, query: &Query) -> Result<FindResult>;
 }
 
+#[cfg(feature = "async")]
+pub type BoxFuture<'a, T> = std::pin::Pin<Box<dyn std::future::Future<Output = T> + Send + 'a>>;
+
+/// Like [`Finder`], for async code. Needs the `async` feature.
+#[cfg(feature = "async")]
+pub trait AsyncFinder {
+    fn find_async<'a>(&'a self, query: &'a Query) -> BoxFuture<'a, Result<FindResult>>;
+}
+
 /////////////////////////////////////////////////////////
 //                    EpisodeNumber                    //
 /////////////////////////////////////////////////////////
-#[derive(Debug, Clone, Copy, Default, PartialEq, Hash)]
+#[derive(Debug, Clone, Default, PartialEq, Hash)]
 pub enum EpisodeNumber {
     #[default]
     All,
     Latest,
+    /// Relative to the latest episode, like `latest-1`
+    BeforeLatest(i32),
     Number(i32),
+    /// Inclusive range, like `1-12`
+    Range(i32, i32),
+    /// Every episode from this one on, like `10-`
+    From(i32),
+    /// Any of several selections, like `3,5,7` or `1-3,10-`
+    List(Vec<EpisodeNumber>),
+    /// A selection within a season, like `S02E03`, `S02E01-06` or `S02`
+    Season(u32, Box<EpisodeNumber>),
 }
 
-impl std::str::FromStr for EpisodeNumber {
-    type Err = String;
+impl EpisodeNumber {
+    /// Whether this selects more than one episode
+    pub fn is_many(&self) -> bool {
+        match self {
+            Self::All | Self::Range(_, _) | Self::From(_) => true,
+            Self::List(items) => items.len() > 1 || items.iter().any(Self::is_many),
+            Self::Season(_, episode) => episode.is_many(),
+            _ => false,
+        }
+    }
 
-    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+    /// Whether `episode` is selected, given the latest episode available.
+    /// Seasons are ignored, see [`EpisodeNumber::selects`] for that.
+    pub fn matches(&self, episode: i32, latest: i32) -> bool {
+        match self {
+            Self::All => true,
+            Self::Latest => episode == latest,
+            Self::BeforeLatest(n) => episode == latest - n,
+            Self::Number(n) => episode == *n,
+            Self::Range(from, to) => (*from..=*to).contains(&episode),
+            Self::From(from) => episode >= *from,
+            Self::List(items) => items.iter().any(|item| item.matches(episode, latest)),
+            Self::Season(_, inner) => inner.matches(episode, latest),
+        }
+    }
+
+    /// Whether the episode at `position` is selected. Season selections are
+    /// compared to its season-relative number, and the rest to its absolute
+    /// number.
+    pub fn selects(&self, position: &EpisodePosition, latest: &LatestEpisodes) -> bool {
+        match self {
+            Self::Season(season, inner) => {
+                position.season == *season
+                    && inner.matches(position.episode, latest.in_season(*season))
+            }
+            Self::List(items) => items.iter().any(|item| item.selects(position, latest)),
+            _ => self.matches(position.absolute, latest.absolute),
+        }
+    }
+
+    /// Parses `s02e03`, `s02e01-06`, `s02e10-`, `s02`, `2x03` and `season 2`
+    fn parse_season(s: &str) -> Option<StdResult<Self, String>> {
+        let (season, episode) = if let Some(season) = s.strip_prefix("season") {
+            (season.trim(), "")
+        } else if let Some((season, episode)) = s.split_once('x') {
+            (season, episode)
+        } else {
+            let rest = s.strip_prefix('s')?;
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            match rest[digits..].strip_prefix('e') {
+                Some(episode) => (&rest[..digits], episode),
+                None if digits == rest.len() => (rest, ""),
+                None => return Some(Err(format!("Invalid episode number {}", s))),
+            }
+        };
+
+        let season = season.parse::<u32>().ok()?;
+        let episode = match episode {
+            "" => Ok(Self::All),
+            episode => Self::parse_single(&episode.replace("-e", "-")),
+        };
+        Some(episode.map(|episode| Self::Season(season, Box::new(episode))))
+    }
+
+    /// Parses anything but lists
+    fn parse_single(s: &str) -> StdResult<Self, String> {
+        let invalid = |_| format!("Invalid episode number {}", s);
+        if let Some(season) = Self::parse_season(s) {
+            return season;
+        }
         match s {
             "latest" => Ok(Self::Latest),
             "all" => Ok(Self::All),
-            _ => s
+            _ if s.starts_with("latest-") => s["latest-".len()..]
+                .trim()
                 .parse::<i32>()
-                .map(Self::Number)
-                .map_err(|_| format!("Invalid episode number {}", s)),
+                .map(Self::BeforeLatest)
+                .map_err(invalid),
+            _ => match s.split_once('-') {
+                Some((from, "")) => from.trim().parse::<i32>().map(Self::From).map_err(invalid),
+                Some((from, to)) => {
+                    let from = from.trim().parse::<i32>().map_err(invalid)?;
+                    let to = to.trim().parse::<i32>().map_err(invalid)?;
+                    if from > to {
+                        return Err(format!("Invalid episode range {}", s));
+                    }
+                    Ok(Self::Range(from, to))
+                }
+                None => s.parse::<i32>().map(Self::Number).map_err(invalid),
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for EpisodeNumber {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        if !s.contains(',') {
+            return Self::parse_single(&s);
+        }
+
+        let items = s
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(Self::parse_single)
+            .collect::<StdResult<Vec<_>, _>>()?;
+        match items.len() {
+            0 => Err(format!("Invalid episode number {}", s)),
+            1 => Ok(items.into_iter().next().unwrap()),
+            _ => Ok(Self::List(items)),
         }
     }
 }
@@ -63,12 +220,43 @@ impl fmt::Display for EpisodeNumber {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> StdResult<(), fmt::Error> {
         match self {
             Self::Number(n) => write!(f, "{}", n),
+            Self::Range(from, to) => write!(f, "{}-{}", from, to),
+            Self::From(from) => write!(f, "{}-", from),
             Self::Latest => write!(f, "latest"),
+            Self::BeforeLatest(n) => write!(f, "latest-{}", n),
             Self::All => write!(f, "all"),
+            Self::List(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            }
+            Self::Season(season, episode) => {
+                write!(f, "S{:02}", season)?;
+                match episode.as_ref() {
+                    Self::All => Ok(()),
+                    Self::Number(n) => write!(f, "E{:02}", n),
+                    Self::Range(from, to) => write!(f, "E{:02}-{:02}", from, to),
+                    Self::From(from) => write!(f, "E{:02}-", from),
+                    episode => write!(f, "E{}", episode),
+                }
+            }
         }
     }
 }
 
+/// Where an episode falls in its show
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EpisodePosition {
+    pub absolute: i32,
+    pub season: u32,
+    /// Relative to the season
+    pub episode: i32,
+}
+
 /////////////////////////////////////////////////
 //                    Query                    //
 /////////////////////////////////////////////////
@@ -77,6 +265,12 @@ pub struct Query {
     pub search: String,
     pub resolution: Option<String>,
     pub episode: EpisodeNumber,
+    /// Used to tell which season absolutely numbered episodes belong to, and
+    /// the other way around
+    pub seasons: SeasonMap,
+    /// Only releases by this group are found, and the latest episode is the
+    /// group's own, even if other groups are ahead
+    pub group: Option<String>,
 }
 
 impl Query {
@@ -85,12 +279,99 @@ impl Query {
             search,
             resolution,
             episode,
+            seasons: SeasonMap::default(),
+            group: None,
+        }
+    }
+
+    pub fn with_group(mut self, group: Option<impl Into<String>>) -> Self {
+        self.group = group.map(Into::into);
+        self
+    }
+
+    /// Whether `key` is by the group the query wants, if it wants one
+    pub fn wants_group(&self, key: &ReleaseKey) -> bool {
+        match &self.group {
+            Some(group) => key
+                .group
+                .as_ref()
+                .is_some_and(|g| g.eq_ignore_ascii_case(group)),
+            None => true,
         }
     }
 
-    pub fn find<F: Finder>(&self, finder: &F) -> Result<FindResult> {
+    pub fn with_seasons(mut self, seasons: SeasonMap) -> Self {
+        self.seasons = seasons;
+        self
+    }
+
+    pub fn find<F: Finder + ?Sized>(&self, finder: &F) -> Result<FindResult> {
         finder.find(self)
     }
+
+    #[cfg(feature = "async")]
+    pub async fn find_async<F: AsyncFinder + ?Sized>(&self, finder: &F) -> Result<FindResult> {
+        finder.find_async(self).await
+    }
+
+    /// Whether a release name contains every word of the search (and the
+    /// resolution, if any) as a whole word, so that "aot" doesn't match
+    /// "Chaotic". Useful for finders that can't search server-side.
+    pub fn matches_name(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        let name_words: HashSet<&str> = words(&name).into_iter().collect();
+        let search = match &self.resolution {
+            Some(resolution) => format!("{} {}", self.search, resolution),
+            None => self.search.clone(),
+        }
+        .to_lowercase();
+        words(&search).iter().all(|word| name_words.contains(word))
+    }
+
+    /// Whether `release` is of the searched show itself, and not of another
+    /// show whose name also has every word of the search, like a spin-off
+    pub fn is_show(&self, release: &ReleaseInfo) -> bool {
+        let title = release.title.as_deref().unwrap_or_default().to_lowercase();
+        words(&title) == words(&self.search.to_lowercase())
+    }
+
+    /// Where `release`, which its source says is episode `episode`, falls in
+    /// the searched show. Releases tagged with a season are mapped
+    /// to absolute numbering and untagged ones to seasons, if there's a
+    /// mapping for the show. Otherwise, untagged releases are in season 1.
+    pub fn position(&self, release: &ReleaseInfo, episode: i32) -> EpisodePosition {
+        match release.season {
+            Some(season) => {
+                let relative = release.episode.unwrap_or(episode);
+                EpisodePosition {
+                    absolute: self
+                        .seasons
+                        .to_absolute(&self.search, season, relative)
+                        .unwrap_or(episode),
+                    season,
+                    episode: relative,
+                }
+            }
+            None => {
+                let (season, relative) = self
+                    .seasons
+                    .to_relative(&self.search, episode)
+                    .unwrap_or((1, episode));
+                EpisodePosition {
+                    absolute: episode,
+                    season,
+                    episode: relative,
+                }
+            }
+        }
+    }
+}
+
+/// The words of `s`, split on anything that isn't a letter or a digit
+fn words(s: &str) -> Vec<&str> {
+    s.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect()
 }
 
 #[derive(Debug, Clone, PartialEq, Hash)]
@@ -99,17 +380,221 @@ pub struct Entry {
     pub bot_id: i64,
     pub bot_name: String,
     pub name: String,
+    /// As the source shows it, like `1.4G`
     pub size: String,
+    /// `size` in bytes, if it could be parsed
+    pub size_bytes: Option<u64>,
+    /// When the source got this entry, if it knows
+    pub uploaded: Option<NaiveDateTime>,
+    /// As reported by the source, or parsed from the name otherwise
+    pub episode: Option<i32>,
+    /// Which finder found this entry
+    pub source: String,
+    /// Where the bot can be found. Entries from the same network usually
+    /// share this
+    pub network: Arc<irc::Config>,
+    /// Whatever else the source knows about this entry, like how many times
+    /// it was downloaded
+    pub extras: BTreeMap<String, String>,
+}
+
+/// Parses sizes like `1.4G`, `350M` or `700 MiB` into bytes
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let unit_start = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let number: f64 = size[..unit_start].parse().ok()?;
+    let multiplier: u64 = match size[unit_start..].trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return None,
+    };
+    Some((number * multiplier as f64) as u64)
+}
+
+impl Entry {
+    /// Group, episode, resolution and so on, parsed from the entry's name
+    pub fn release(&self) -> ReleaseInfo {
+        ReleaseInfo::parse(&self.name)
+    }
+
+    /// A Nibl entry for tests, with the episode parsed from its name
+    #[cfg(test)]
+    pub(crate) fn for_tests(name: &str, bot: &str, size: &str) -> Self {
+        Self {
+            package_number: 1,
+            bot_id: 0,
+            bot_name: bot.to_owned(),
+            name: name.to_owned(),
+            size: size.to_owned(),
+            size_bytes: parse_size(size),
+            uploaded: None,
+            episode: ReleaseInfo::parse(name).episode,
+            source: "nibl".to_owned(),
+            network: NIBL_CONFIG.clone(),
+            extras: Default::default(),
+        }
+    }
 }
 
 impl fmt::Display for Entry {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> StdResult<(), fmt::Error> {
         write!(
             f,
-            "{} [{}] ({})",
+            "{} [{}] ({}) {}",
             self.name,
             self.bot_name.yellow(),
             self.size,
+            self.source.dimmed(),
         )
     }
 }
+
+/// A stand-in for a finder in tests. Finds entries named after what
+/// `respond` gives for each search, all of them 1.2G and from Bot, along
+/// with the warnings it gives.
+#[cfg(test)]
+pub(crate) struct StandIn(pub fn(&str) -> Result<StandInResult>);
+
+#[cfg(test)]
+pub(crate) type StandInResult = (Vec<&'static str>, Vec<String>);
+
+#[cfg(test)]
+impl Finder for StandIn {
+    fn find(&self, query: &Query) -> Result<FindResult> {
+        let (names, warnings) = (self.0)(&query.search)?;
+        Ok(FindResult {
+            entries: names
+                .into_iter()
+                .map(|name| Entry::for_tests(name, "Bot", "1.2G"))
+                .collect(),
+            warnings,
+            notes: Vec::new(),
+        })
+    }
+}
+
+/// A stand-in for a website in tests. Answers every request with what
+/// `respond` gives for its path, and returns the server's URL.
+#[cfg(test)]
+pub(crate) fn stand_in(respond: fn(&str) -> String) -> String {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for mut client in listener.incoming().flatten() {
+            let mut lines = BufReader::new(client.try_clone().unwrap()).lines();
+            let request = lines.next().unwrap().unwrap();
+            while lines.next().is_some_and(|line| !line.unwrap().is_empty()) {}
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            let body = respond(path);
+            write!(
+                client,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use EpisodeNumber::*;
+
+    fn season(season: u32, episode: EpisodeNumber) -> EpisodeNumber {
+        Season(season, Box::new(episode))
+    }
+
+    #[test]
+    fn episode_numbers() {
+        let cases = [
+            ("5", Number(5)),
+            ("1-12", Range(1, 12)),
+            ("10-", From(10)),
+            ("latest", Latest),
+            ("latest-1", BeforeLatest(1)),
+            ("all", All),
+            ("3, 5,7", List(vec![Number(3), Number(5), Number(7)])),
+            ("S02E03", season(2, Number(3))),
+            ("S2E03", season(2, Number(3))),
+            ("s02e01-06", season(2, Range(1, 6))),
+            ("S02E01-E06", season(2, Range(1, 6))),
+            ("S02E10-", season(2, From(10))),
+            ("S02", season(2, All)),
+            ("2x03", season(2, Number(3))),
+            ("2x01-06", season(2, Range(1, 6))),
+            ("Season 2", season(2, All)),
+            (
+                "S01E12,S02E01",
+                List(vec![season(1, Number(12)), season(2, Number(1))]),
+            ),
+        ];
+        for (s, expected) in cases {
+            assert_eq!(s.parse::<EpisodeNumber>(), Ok(expected), "{}", s);
+        }
+
+        for invalid in [
+            "",
+            "5-1",
+            "S02X03",
+            "S02F03",
+            "Season two",
+            "latest-x",
+            "x03",
+        ] {
+            assert!(invalid.parse::<EpisodeNumber>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn seasons_round_trip() {
+        for s in ["S02E03", "S02E01-06", "S02E10-", "S02", "S01E12,S02E01"] {
+            assert_eq!(s.parse::<EpisodeNumber>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn season_positions() {
+        let seasons: SeasonMap = r#""Shingeki no Kyojin" = { 1 = 1, 2 = 26 }"#.parse().unwrap();
+        let query = Query::new("Shingeki no Kyojin".into(), None, "S02E03".parse().unwrap())
+            .with_seasons(seasons);
+        let latest = LatestEpisodes::default();
+
+        for name in [
+            "[Group] Shingeki no Kyojin - 28 (1080p).mkv",
+            "[Group] Shingeki no Kyojin S2 - 03 (1080p).mkv",
+            "[Group] Shingeki no Kyojin Season 2 - 03 (1080p).mkv",
+        ] {
+            let release = ReleaseInfo::parse(name);
+            let position = query.position(&release, release.episode.unwrap());
+            assert!(query.episode.selects(&position, &latest), "{}", name);
+        }
+    }
+
+    #[test]
+    fn names_match_whole_words() {
+        let query = |search: &str, resolution: Option<&str>| {
+            Query::new(search.into(), resolution.map(Into::into), All)
+        };
+        let aot = query("aot", None);
+        assert!(!aot.matches_name("[SubsPlease] Chaotic Child - 01 (1080p).mkv"));
+        assert!(aot.matches_name("[Group] AoT - 01.mkv"));
+
+        let frieren = query("Sousou no Frieren", Some("1080p"));
+        assert!(frieren.matches_name("[SubsPlease] Sousou no Frieren - 01 (1080p) [F02B9CEE].mkv"));
+        assert!(frieren.matches_name("Sousou.no.Frieren.S01E01.1080p.WEB.mkv"));
+        assert!(!frieren.matches_name("[SubsPlease] Sousou no Frieren - 01 (720p).mkv"));
+        assert!(!query("Re:Zero", None).matches_name("[Group] Rezero - 01.mkv"));
+        assert!(query("Re:Zero", None).matches_name("[Group] Re Zero kara - 01.mkv"));
+    }
+}
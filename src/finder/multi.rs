@@ -0,0 +1,181 @@
+//! Searches several finders at once and merges what they find.
+
+use super::{FindResult, Finder, Query, Result};
+use std::{collections::HashSet, thread};
+
+#[derive(Default)]
+pub struct Multi {
+    finders: Vec<(String, Box<dyn Finder + Send + Sync>)>,
+}
+
+impl Multi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a finder, whose entries will be tagged with `source`. Entries
+    /// found by earlier finders take precedence over duplicates found later.
+    pub fn with(
+        mut self,
+        source: impl Into<String>,
+        finder: impl Finder + Send + Sync + 'static,
+    ) -> Self {
+        self.finders.push((source.into(), Box::new(finder)));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.finders.is_empty()
+    }
+}
+
+impl Finder for Multi {
+    /// Runs every finder in parallel. Sources that fail only produce a
+    /// warning, unless all of them fail.
+    fn find(&self, query: &Query) -> Result<FindResult> {
+        let results: Vec<(&str, Result<FindResult>)> = thread::scope(|s| {
+            let handles: Vec<_> = self
+                .finders
+                .iter()
+                .map(|(source, finder)| (source.as_str(), s.spawn(|| finder.find(query))))
+                .collect();
+            handles
+                .into_iter()
+                .map(|(source, handle)| (source, handle.join().unwrap()))
+                .collect()
+        });
+
+        let mut merged: Option<FindResult> = None;
+        let mut warnings = Vec::new();
+        let mut notes = Vec::new();
+        let mut first_error = None;
+        let mut seen = HashSet::new();
+
+        for (source, result) in results {
+            let result = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    warnings.push(format!("{} failed: {}", source, e));
+                    first_error.get_or_insert(e);
+                    continue;
+                }
+            };
+            warnings.extend(
+                result
+                    .warnings
+                    .into_iter()
+                    .map(|w| format!("{}: {}", source, w)),
+            );
+            notes.extend(
+                result
+                    .notes
+                    .into_iter()
+                    .map(|n| format!("{}: {}", source, n)),
+            );
+
+            let merged = merged.get_or_insert_with(|| FindResult {
+                entries: Vec::new(),
+                warnings: Vec::new(),
+                notes: Vec::new(),
+            });
+
+            for mut entry in result.entries {
+                // The same file offered by several bots is only listed once
+                if seen.insert((entry.name.to_lowercase(), entry.size.to_lowercase())) {
+                    entry.source = source.to_owned();
+                    merged.entries.push(entry);
+                }
+            }
+        }
+
+        match (merged, first_error) {
+            (Some(mut merged), _) => {
+                merged.warnings = warnings;
+                merged.notes = notes;
+                Ok(merged)
+            }
+            (None, Some(e)) => Err(e),
+            (None, None) => Err(super::Error::Config("No finders were configured".into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::finder::{Entry, EpisodeNumber, Error, StandIn};
+
+    /// Offers every one of its files from one bot, which is slow
+    struct Bot(&'static str, &'static [&'static str]);
+
+    impl Finder for Bot {
+        fn find(&self, _: &Query) -> Result<FindResult> {
+            Ok(FindResult {
+                entries: self
+                    .1
+                    .iter()
+                    .map(|name| Entry::for_tests(name, self.0, "1.2G"))
+                    .collect(),
+                warnings: vec![format!("{} is slow", self.0)],
+                notes: vec![format!("{} has {} files", self.0, self.1.len())],
+            })
+        }
+    }
+
+    fn query() -> Query {
+        Query::new("Show".into(), None, EpisodeNumber::All)
+    }
+
+    #[test]
+    fn merges_every_source() {
+        let multi = Multi::new()
+            .with(
+                "nibl",
+                Bot("A", &["[G] Show - 01.mkv", "[G] Show - 02.mkv"]),
+            )
+            .with(
+                "xdcc list",
+                Bot("B", &["[g] show - 01.MKV", "[G] Show - 03.mkv"]),
+            );
+        let result = multi.find(&query()).unwrap();
+
+        let found: Vec<_> = result
+            .entries
+            .iter()
+            .map(|e| (e.source.as_str(), e.bot_name.as_str(), e.name.as_str()))
+            .collect();
+        // B's episode 1 is the same file as A's, so only A's is listed
+        assert_eq!(
+            found,
+            [
+                ("nibl", "A", "[G] Show - 01.mkv"),
+                ("nibl", "A", "[G] Show - 02.mkv"),
+                ("xdcc list", "B", "[G] Show - 03.mkv"),
+            ]
+        );
+        assert_eq!(result.warnings, ["nibl: A is slow", "xdcc list: B is slow"]);
+        assert_eq!(
+            result.notes,
+            ["nibl: A has 2 files", "xdcc list: B has 2 files"]
+        );
+    }
+
+    #[test]
+    fn failing_sources_only_warn() {
+        let down = || StandIn(|_| Err(Error::Config("down".into())));
+        let multi = Multi::new()
+            .with("nibl", down())
+            .with("packlist", Bot("A", &["[G] Show - 01.mkv"]));
+        let result = multi.find(&query()).unwrap();
+        assert_eq!(result.entries.len(), 1);
+        assert_eq!(result.entries[0].source, "packlist");
+        assert_eq!(
+            result.warnings,
+            ["nibl failed: down", "packlist: A is slow"]
+        );
+
+        let multi = Multi::new().with("nibl", down()).with("packlist", down());
+        assert!(matches!(multi.find(&query()), Err(Error::Config(e)) if e == "down"));
+        assert!(Multi::new().find(&query()).is_err());
+    }
+}
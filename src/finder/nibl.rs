@@ -1,119 +1,464 @@
-use super::{EpisodeNumber, Error, Finder, Result};
-use crate::downloader::irc;
+use super::{EpisodeNumber, Error, Finder, Latest, ReleaseKey, Result, Upload};
+use crate::{cache::Cache, downloader::irc, release::ReleaseInfo};
+use chrono::{DateTime, NaiveDateTime};
 use lazy_static::lazy_static;
-use rand::prelude::*;
-use serde::Deserialize;
-use std::collections::HashMap;
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+    thread,
+    time::Duration,
+};
 
+/// Where the API is, unless a mirror is given with [`NiblBuilder::base_url`]
 pub const API_BASE: &str = "https://api.nibl.co.uk/nibl";
 
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Requests failing with a connection error or a 5xx are retried this many
+/// times, waiting twice as long before each retry
+pub const DEFAULT_RETRIES: u32 = 2;
+pub const DEFAULT_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The wait between retries stops doubling once it gets this long
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The bot list barely changes, so it's cached for a while
+pub const BOTS_TTL: Duration = Duration::from_secs(60 * 60 * 6);
+
+/// Search results are only cached for a bit, to avoid hitting the API again
+/// while tweaking filters
+pub const SEARCH_TTL: Duration = Duration::from_secs(60 * 10);
+
+/// The latest packs change all the time, so they're barely cached
+pub const LATEST_TTL: Duration = Duration::from_secs(60 * 2);
+
 lazy_static! {
-    pub static ref NIBL_CONFIG: irc::Config = irc::Config {
+    pub static ref NIBL_CONFIG: Arc<irc::Config> = Arc::new(irc::Config {
         server: "irc.rizon.net:6667".into(),
         channel: "#nibl".into(),
-        nickname: format!("real-person-{:x}", thread_rng().gen::<u32>()),
-    };
+        nickname: irc::random_nickname(),
+    });
 }
 
 pub struct Nibl {
-    client: reqwest::blocking::Client,
+    /// Built on first use, since building or dropping one panics inside an
+    /// async runtime, where only `async_client` is used
+    client: OnceLock<reqwest::blocking::Client>,
+    timeout: Option<Duration>,
+    user_agent: String,
+    #[cfg(feature = "async")]
+    async_client: reqwest::Client,
+    base_url: String,
+    retries: u32,
+    backoff: Duration,
+    cache: Option<Cache>,
+    refresh: bool,
 }
 
 impl Default for Nibl {
+    fn default() -> Self {
+        Self::builder().build().unwrap()
+    }
+}
+
+/// Builds a [`Nibl`] with custom HTTP settings
+#[derive(Debug, Clone)]
+pub struct NiblBuilder {
+    base_url: String,
+    timeout: Option<Duration>,
+    user_agent: String,
+    retries: u32,
+    backoff: Duration,
+    cache: Option<Cache>,
+}
+
+impl Default for NiblBuilder {
     fn default() -> Self {
         Self {
-            client: reqwest::blocking::Client::builder()
-                .use_rustls_tls()
-                .build()
-                .unwrap(),
+            base_url: API_BASE.into(),
+            timeout: Some(DEFAULT_TIMEOUT),
+            user_agent: concat!("mahou/", env!("CARGO_PKG_VERSION")).into(),
+            retries: DEFAULT_RETRIES,
+            backoff: DEFAULT_BACKOFF,
+            cache: None,
         }
     }
 }
 
-impl Finder for Nibl {
-    fn find(&self, query: &super::Query) -> Result<super::FindResult> {
-        let packages = self.search_packages(query)?;
-        let bots = self.get_bots()?;
+impl NiblBuilder {
+    /// For mirrors, or a local stand-in of the API
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// How long a request can take, or `None` to wait forever
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
 
-        let latest_episode = packages
-            .iter()
-            .max_by_key(|p| &p.last_modified)
-            .map(|p| p.episode_number)
-            .unwrap_or(1);
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
 
-        let filter_episode = |p: &Package| match query.episode {
-            EpisodeNumber::All => true,
-            EpisodeNumber::Latest => p.episode_number == latest_episode,
-            EpisodeNumber::Number(n) => p.episode_number == n,
-        };
+    /// How many times to retry a request that failed with a connection error
+    /// or a 5xx
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
 
-        let make_entry = |p: Package| super::Entry {
-            package_number: p.number,
-            bot_id: p.bot_id,
-            bot_name: bots
-                .get(&p.bot_id)
-                .map(|b| b.name.clone())
-                .unwrap_or("unknown bot?".into()),
-            name: p.name,
-            size: p.size,
+    /// How long to wait before the first retry. It doubles after each one.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Caches responses in `cache`, keyed by their full URL, so that a
+    /// mirror's responses are never used for another. Nothing is cached
+    /// unless a cache is given.
+    pub fn cache(mut self, cache: Option<Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn build(self) -> Result<Nibl> {
+        #[cfg(feature = "async")]
+        let async_client = {
+            let mut client = reqwest::Client::builder()
+                .use_rustls_tls()
+                .user_agent(&self.user_agent);
+            if let Some(timeout) = self.timeout {
+                client = client.timeout(timeout).connect_timeout(timeout);
+            }
+            client.build()?
         };
 
-        let entries: Vec<super::Entry> = packages
-            .into_iter()
-            .filter(filter_episode)
-            .map(make_entry)
-            .collect();
+        Ok(Nibl {
+            client: OnceLock::new(),
+            timeout: self.timeout,
+            user_agent: self.user_agent,
+            #[cfg(feature = "async")]
+            async_client,
+            base_url: self.base_url,
+            retries: self.retries,
+            backoff: self.backoff,
+            cache: self.cache,
+            refresh: false,
+        })
+    }
+}
+
+impl Finder for Nibl {
+    fn find(&self, query: &super::Query) -> Result<super::FindResult> {
+        let packages = self.search_packages(query)?;
+        let bots = self.get_bots()?;
+        Ok(found(query, packages, &bots))
+    }
+}
 
-        Ok(super::FindResult {
-            irc_config: NIBL_CONFIG.clone(),
-            entries,
+#[cfg(feature = "async")]
+impl super::AsyncFinder for Nibl {
+    fn find_async<'a>(
+        &'a self,
+        query: &'a super::Query,
+    ) -> super::BoxFuture<'a, Result<super::FindResult>> {
+        Box::pin(async move {
+            let (packages, bots) =
+                tokio::try_join!(self.search_packages_async(query), self.get_bots_async())?;
+            Ok(found(query, packages, &bots))
         })
     }
 }
 
 impl Nibl {
+    pub fn builder() -> NiblBuilder {
+        NiblBuilder::default()
+    }
+
+    /// Ignores cached responses, but still caches the new ones
+    pub fn with_refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Limits how much disk space cached responses can take
+    pub fn with_cache_size(mut self, max_size: u64) -> Self {
+        self.cache = self.cache.map(|cache| cache.with_max_size(max_size));
+        self
+    }
+
     pub fn search_packages(&self, query: &super::Query) -> Result<Vec<Package>> {
-        let mut url = format!("{}/search?query={}", API_BASE, query.search);
-        if let Some(resolution) = &query.resolution {
-            url += &format!("%20{}", resolution);
+        self.get(&self.search_url(query)?, SEARCH_TTL)
+    }
+
+    fn search_url(&self, query: &super::Query) -> Result<String> {
+        let search = match &query.resolution {
+            Some(resolution) => format!("{} {}", query.search, resolution),
+            None => query.search.clone(),
+        };
+        let mut params = vec![("query", search)];
+        // Episode numbers are left for `find` to check if the show has a
+        // season mapping, since Nibl only knows how each release numbers it
+        if let (EpisodeNumber::Number(episode), false) =
+            (&query.episode, query.seasons.contains(&query.search))
+        {
+            params.push(("episodeNumber", episode.to_string()));
         }
-        if let EpisodeNumber::Number(episode) = query.episode {
-            url += &format!("&episodeNumber={}", episode);
+        let url = format!("{}/search", self.base_url);
+        reqwest::Url::parse_with_params(&url, &params)
+            .map(String::from)
+            .map_err(|e| Error::Config(format!("Invalid Nibl URL {}: {}", self.base_url, e)))
+    }
+
+    /// The packs most recently added to Nibl, newest first
+    pub fn latest(&self, count: usize) -> Result<Vec<super::Entry>> {
+        let url = format!("{}/latest?size={}", self.base_url, count);
+        let packages: Vec<Package> = self.get(&url, LATEST_TTL)?;
+        let bots = self.get_bots()?;
+        Ok(packages.into_iter().map(|p| make_entry(p, &bots)).collect())
+    }
+
+    pub fn get_bots(&self) -> Result<HashMap<i64, Bot>> {
+        let bots: Vec<Bot> = self.get(&format!("{}/bots", self.base_url), BOTS_TTL)?;
+        Ok(bots.into_iter().map(|bot| (bot.id, bot)).collect())
+    }
+
+    /// Whether `nick` is one of Nibl's bots. The bot list is cached, so this
+    /// is cheap enough to ask for every announcement.
+    pub fn is_bot(&self, nick: &str) -> Result<bool> {
+        let bots = self.get_bots()?;
+        Ok(bots.values().any(|bot| bot.name.eq_ignore_ascii_case(nick)))
+    }
+
+    /// GETs an API endpoint, going through the cache. Only successful
+    /// responses are cached.
+    fn get<T: DeserializeOwned>(&self, url: &str, ttl: Duration) -> Result<T> {
+        let cache = self.cache.as_ref().filter(|_| !self.refresh);
+        if let Some(content) = cache
+            .and_then(|c| c.get(url, ttl))
+            .and_then(|b| content(&b).ok())
+        {
+            return Ok(content);
         }
+        let body = self.fetch(url)?;
+        let content = content(&body)?;
+        if let Some(cache) = &self.cache {
+            cache.put(url, &body);
+        }
+        Ok(content)
+    }
 
-        let response = self.client.get(&url).send()?;
-        let search_result: SearchResult = response.json()?;
-        if search_result.status != "OK" {
-            return Err(Error::APIError {
-                api: "nibl",
-                message: search_result.message,
-            });
+    fn client(&self) -> Result<&reqwest::blocking::Client> {
+        if let Some(client) = self.client.get() {
+            return Ok(client);
+        }
+        let mut client = reqwest::blocking::Client::builder()
+            .use_rustls_tls()
+            .user_agent(&self.user_agent)
+            .timeout(self.timeout);
+        if let Some(timeout) = self.timeout {
+            client = client.connect_timeout(timeout);
         }
-        Ok(search_result.content)
+        let client = client.build()?;
+        Ok(self.client.get_or_init(|| client))
     }
 
-    pub fn get_bots(&self) -> Result<HashMap<i64, Bot>> {
-        let response = self.client.get(format!("{}/bots", API_BASE)).send()?;
-        let result: BotList = response.json()?;
-        if result.status != "OK" {
-            return Err(Error::APIError {
+    /// GETs `url`, retrying with exponential backoff when the API seems to be
+    /// temporarily down
+    fn fetch(&self, url: &str) -> Result<String> {
+        let client = self.client()?;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let reason = match client.get(url).send() {
+                Ok(response) if response.status().is_server_error() => {
+                    format!("HTTP {}", response.status())
+                }
+                Ok(response) if response.status().is_client_error() => {
+                    return Err(Error::APIError {
+                        api: "nibl",
+                        message: format!("HTTP {}", response.status()),
+                    });
+                }
+                Ok(response) => return Ok(response.text()?),
+                Err(e) if is_transient(&e) => e.to_string(),
+                Err(e) => return Err(e.into()),
+            };
+            thread::sleep(self.backoff_for(attempts, reason)?);
+        }
+    }
+
+    /// How long to wait before retrying a request that failed for `reason`,
+    /// or an error if there have been enough attempts
+    fn backoff_for(&self, attempts: u32, reason: String) -> Result<Duration> {
+        if attempts > self.retries {
+            return Err(Error::Unavailable {
                 api: "nibl",
-                message: result.message,
+                attempts,
+                reason,
             });
         }
-        Ok(result
-            .content
-            .into_iter()
-            .map(|bot| (bot.id, bot))
-            .collect())
+        let factor = 2u32.saturating_pow(attempts - 1);
+        Ok(self.backoff.saturating_mul(factor).min(MAX_BACKOFF))
     }
 }
 
+#[cfg(feature = "async")]
+impl Nibl {
+    pub async fn search_packages_async(&self, query: &super::Query) -> Result<Vec<Package>> {
+        self.get_async(&self.search_url(query)?, SEARCH_TTL).await
+    }
+
+    pub async fn get_bots_async(&self) -> Result<HashMap<i64, Bot>> {
+        let url = format!("{}/bots", self.base_url);
+        let bots: Vec<Bot> = self.get_async(&url, BOTS_TTL).await?;
+        Ok(bots.into_iter().map(|bot| (bot.id, bot)).collect())
+    }
+
+    /// Like [`Nibl::get`], but the cache is read and written on a blocking
+    /// thread, so that waiting for the disk doesn't stall the runtime
+    async fn get_async<T: DeserializeOwned>(&self, url: &str, ttl: Duration) -> Result<T> {
+        let cache = self.cache.clone().filter(|_| !self.refresh);
+        if let Some(cache) = cache {
+            let key = url.to_owned();
+            let cached = tokio::task::spawn_blocking(move || cache.get(&key, ttl)).await;
+            if let Some(content) = cached.ok().flatten().and_then(|b| content(&b).ok()) {
+                return Ok(content);
+            }
+        }
+        let body = self.fetch_async(url).await?;
+        let content = content(&body)?;
+        if let Some(cache) = self.cache.clone() {
+            let key = url.to_owned();
+            // Failing to cache it only means fetching it again next time
+            tokio::task::spawn_blocking(move || cache.put(&key, &body))
+                .await
+                .ok();
+        }
+        Ok(content)
+    }
+
+    async fn fetch_async(&self, url: &str) -> Result<String> {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let reason = match self.async_client.get(url).send().await {
+                Ok(response) if response.status().is_server_error() => {
+                    format!("HTTP {}", response.status())
+                }
+                Ok(response) if response.status().is_client_error() => {
+                    return Err(Error::APIError {
+                        api: "nibl",
+                        message: format!("HTTP {}", response.status()),
+                    });
+                }
+                Ok(response) => return Ok(response.text().await?),
+                Err(e) if is_transient(&e) => e.to_string(),
+                Err(e) => return Err(e.into()),
+            };
+            tokio::time::sleep(self.backoff_for(attempts, reason)?).await;
+        }
+    }
+}
+
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout()
+}
+
+/// Picks the packages that match `query`
+fn found(
+    query: &super::Query,
+    packages: Vec<Package>,
+    bots: &HashMap<i64, Bot>,
+) -> super::FindResult {
+    let uploads: Vec<Upload> = packages
+        .iter()
+        .map(|p| {
+            let release = ReleaseInfo::parse(&p.name);
+            Upload {
+                key: ReleaseKey::of(&release),
+                position: query.position(&release, p.episode_number),
+                uploaded: parse_timestamp(&p.last_modified),
+            }
+        })
+        .collect();
+    let latest = Latest::new(&uploads);
+
+    let filter_episode = |(upload, _): &(Upload, Package)| {
+        query.wants_group(&upload.key)
+            && query
+                .episode
+                .selects(&upload.position, latest.of(&upload.key, query))
+    };
+
+    let entries: Vec<super::Entry> = uploads
+        .into_iter()
+        .zip(packages)
+        .filter(filter_episode)
+        .map(|(_, p)| make_entry(p, bots))
+        .collect();
+
+    super::FindResult {
+        entries,
+        warnings: Vec::new(),
+        notes: latest.notes,
+    }
+}
+
+fn make_entry(p: Package, bots: &HashMap<i64, Bot>) -> super::Entry {
+    super::Entry {
+        package_number: p.number,
+        bot_id: p.bot_id,
+        bot_name: bots
+            .get(&p.bot_id)
+            .map(|b| b.name.clone())
+            .unwrap_or("unknown bot?".into()),
+        // Nibl says -1 when it doesn't know
+        episode: Some(p.episode_number)
+            .filter(|&episode| episode >= 0)
+            .or_else(|| ReleaseInfo::parse(&p.name).episode),
+        name: p.name,
+        size_bytes: super::parse_size(&p.size),
+        size: p.size,
+        uploaded: parse_timestamp(&p.last_modified),
+        source: "nibl".into(),
+        network: NIBL_CONFIG.clone(),
+        extras: [("last_modified".to_owned(), p.last_modified)].into(),
+    }
+}
+
+/// Nibl's timestamps look like `2023-05-01 12:34:56`, but ISO 8601 ones are
+/// accepted too, just in case
+/// What an API response holds, if the API says it's OK
+fn content<T: DeserializeOwned>(body: &str) -> Result<T> {
+    let response: Response<T> = serde_json::from_str(body)?;
+    if response.status != "OK" {
+        return Err(Error::APIError {
+            api: "nibl",
+            message: response.message,
+        });
+    }
+    Ok(response.content)
+}
+
+fn parse_timestamp(s: &str) -> Option<NaiveDateTime> {
+    let s = s.trim();
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f"))
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f"))
+        .ok()
+}
+
 #[derive(Deserialize)]
-struct BotList {
+struct Response<T> {
     status: String,
     message: String,
-    content: Vec<Bot>,
+    content: T,
 }
 
 #[derive(Deserialize)]
@@ -133,9 +478,171 @@ pub struct Package {
     episode_number: i32,
 }
 
-#[derive(Deserialize)]
-struct SearchResult {
-    status: String,
-    message: String,
-    content: Vec<Package>,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        let expected = chrono::NaiveDate::from_ymd_opt(2023, 5, 1)
+            .unwrap()
+            .and_hms_opt(12, 34, 56);
+        assert_eq!(parse_timestamp("2023-05-01 12:34:56"), expected);
+        assert_eq!(parse_timestamp(" 2023-05-01T12:34:56 "), expected);
+        assert_eq!(parse_timestamp("2023-05-01T12:34:56Z"), expected);
+        assert_eq!(parse_timestamp("2023-05-01T14:34:56+02:00"), expected);
+        assert!(parse_timestamp("2023-05-01 12:34:56.789").is_some());
+        assert_eq!(parse_timestamp("yesterday"), None);
+        assert_eq!(parse_timestamp(""), None);
+    }
+
+    #[test]
+    fn search_urls_are_encoded() {
+        use super::super::Query;
+
+        let nibl = Nibl::builder()
+            .base_url("https://nibl.example/api/")
+            .build()
+            .unwrap();
+        let url = |search: &str, resolution: Option<&str>, episode| {
+            let query = Query::new(search.into(), resolution.map(Into::into), episode);
+            nibl.search_url(&query).unwrap()
+        };
+        assert_eq!(
+            url("Show & Friends #1+?", None, EpisodeNumber::All),
+            "https://nibl.example/api/search?query=Show+%26+Friends+%231%2B%3F"
+        );
+        assert_eq!(
+            url("Show", Some("1080p"), EpisodeNumber::Number(3)),
+            "https://nibl.example/api/search?query=Show+1080p&episodeNumber=3"
+        );
+
+        let broken = Nibl::builder().base_url("not a url").build().unwrap();
+        let query = Query::new("Show".into(), None, EpisodeNumber::All);
+        assert!(broken.search_url(&query).is_err());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_a_limit() {
+        let nibl = Nibl::builder()
+            .retries(100)
+            .backoff(Duration::from_secs(1))
+            .build()
+            .unwrap();
+        let backoff = |attempts| nibl.backoff_for(attempts, String::new()).unwrap();
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(3), Duration::from_secs(4));
+        assert_eq!(backoff(40), MAX_BACKOFF);
+        assert_eq!(backoff(100), MAX_BACKOFF);
+        assert!(nibl.backoff_for(101, String::new()).is_err());
+    }
+
+    #[test]
+    fn latest_is_the_same_for_every_group() {
+        use super::super::Query;
+
+        let url = super::super::stand_in(|path| {
+            let content = match path.starts_with("/bots") {
+                true => r#"[{"id": 1, "name": "Bot"}]"#,
+                false => {
+                    r#"[
+                    {"botId": 1, "number": 1, "name": "[G] Show - 05 (1080p).mkv",
+                    "size": "1.4G", "lastModified": "2023-05-08 12:00:00", "episodeNumber": 5},
+                    {"botId": 1, "number": 2, "name": "[Other] Show - 06 (1080p).mkv",
+                    "size": "1.4G", "lastModified": "2023-05-01 12:00:00", "episodeNumber": 6},
+                    {"botId": 1, "number": 3, "name": "[Other] Show - 07 (1080p).mkv",
+                    "size": "1.4G", "lastModified": "2023-05-08 12:00:00", "episodeNumber": 7},
+                    {"botId": 1, "number": 4, "name": "[Other] Show - 07 (720p).mkv",
+                    "size": "700M", "lastModified": "2023-05-08 12:00:00", "episodeNumber": 7}
+                    ]"#
+                }
+            };
+            format!(
+                r#"{{"status": "OK", "message": "", "content": {}}}"#,
+                content
+            )
+        });
+        let nibl = Nibl::builder().base_url(url).build().unwrap();
+        let query = Query::new("Show".into(), None, EpisodeNumber::Latest);
+        let result = query.find(&nibl).unwrap();
+        // [G] is behind, so its episode 5 isn't the latest
+        let numbers: Vec<_> = result.entries.iter().map(|e| e.package_number).collect();
+        assert_eq!(numbers, [3, 4]);
+
+        let query = Query::new("Show".into(), None, EpisodeNumber::BeforeLatest(1));
+        let result = query.find(&nibl).unwrap();
+        assert_eq!(result.entries[0].package_number, 2);
+        assert_eq!(result.entries.len(), 1);
+
+        // Unless only [G]'s releases are wanted, like with --group
+        let query = Query::new("Show".into(), None, EpisodeNumber::Latest).with_group(Some("g"));
+        let result = query.find(&nibl).unwrap();
+        let numbers: Vec<_> = result.entries.iter().map(|e| e.package_number).collect();
+        assert_eq!(numbers, [1]);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn finds_inside_a_runtime() {
+        use super::super::{AsyncFinder, Query};
+
+        let url = super::super::stand_in(|path| {
+            let content = match path.starts_with("/bots") {
+                true => r#"[{"id": 1, "name": "Bot"}]"#,
+                false => {
+                    r#"[{"botId": 1, "number": 7, "name": "[G] Show - 03 (1080p).mkv",
+                    "size": "1.4G", "lastModified": "2023-05-01 12:34:56", "episodeNumber": 3}]"#
+                }
+            };
+            format!(
+                r#"{{"status": "OK", "message": "", "content": {}}}"#,
+                content
+            )
+        });
+        let nibl = Nibl::builder().base_url(url).build().unwrap();
+        let query = Query::new("Show".into(), None, EpisodeNumber::All);
+        let result = nibl.find_async(&query).await.unwrap();
+        assert_eq!(result.entries.len(), 1);
+        assert_eq!(result.entries[0].bot_name, "Bot");
+        assert_eq!(result.entries[0].package_number, 7);
+        assert_eq!(result.entries[0].episode, Some(3));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn caches_inside_a_runtime() {
+        use super::super::{AsyncFinder, Query};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+        let url = super::super::stand_in(|path| {
+            REQUESTS.fetch_add(1, Ordering::SeqCst);
+            let content = match path.starts_with("/bots") {
+                true => r#"[{"id": 1, "name": "Bot"}]"#,
+                false => {
+                    r#"[{"botId": 1, "number": 7, "name": "[G] Show - 03 (1080p).mkv",
+                    "size": "1.4G", "lastModified": "2023-05-01 12:34:56", "episodeNumber": 3}]"#
+                }
+            };
+            format!(
+                r#"{{"status": "OK", "message": "", "content": {}}}"#,
+                content
+            )
+        });
+        let dir = std::env::temp_dir().join(format!("mahou-nibl-async-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        let nibl = Nibl::builder()
+            .base_url(url)
+            .cache(Cache::open_in(&dir))
+            .build()
+            .unwrap();
+        let query = Query::new("Show".into(), None, EpisodeNumber::All);
+        for _ in 0..2 {
+            let result = nibl.find_async(&query).await.unwrap();
+            assert_eq!(result.entries.len(), 1);
+        }
+        // The bot list and the search, only fetched the first time
+        assert_eq!(REQUESTS.load(Ordering::SeqCst), 2);
+        std::fs::remove_dir_all(&dir).ok();
+    }
 }
@@ -0,0 +1,199 @@
+//! Finds packs in the packlists that many bots publish on the web, in iroffer's
+//! text, XML or HTML formats.
+
+use super::{iroffer, FindResult, Finder, Query, Result};
+use crate::{cache::Cache, downloader::irc};
+use std::{str::FromStr, time::Duration};
+
+/// How long a packlist is reused before getting it again. Bots add packs
+/// often, so this is a lot shorter than for lists asked for over IRC.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 30);
+
+/// How long to wait for a packlist's website
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A packlist URL and the bot it belongs to, written as
+/// `https://example.org/packlist.txt=bot@server:port/#channel`
+#[derive(Debug, Clone)]
+pub struct PacklistSource {
+    pub url: String,
+    pub address: irc::BotAddress,
+}
+
+impl FromStr for PacklistSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (url, address) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("Invalid packlist {} (expected URL=bot@server/#channel)", s))?;
+        Ok(Self {
+            url: url.to_owned(),
+            address: address.parse()?,
+        })
+    }
+}
+
+pub struct Packlist {
+    client: reqwest::blocking::Client,
+    sources: Vec<PacklistSource>,
+    cache: Option<Cache>,
+    ttl: Duration,
+    refresh: bool,
+}
+
+impl Packlist {
+    pub fn new(sources: Vec<PacklistSource>) -> Self {
+        Self {
+            client: reqwest::blocking::Client::builder()
+                .use_rustls_tls()
+                .timeout(TIMEOUT)
+                .connect_timeout(TIMEOUT)
+                .build()
+                .unwrap(),
+            sources,
+            cache: None,
+            ttl: DEFAULT_TTL,
+            refresh: false,
+        }
+    }
+
+    /// Caches packlists in `cache`, which nothing is by default
+    pub fn with_cache(mut self, cache: Option<Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Gets packlists again, even if they're cached
+    pub fn with_refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Limits how much disk space cached packlists can take
+    pub fn with_cache_size(mut self, max_size: u64) -> Self {
+        self.cache = self.cache.map(|cache| cache.with_max_size(max_size));
+        self
+    }
+
+    /// The packs in a packlist, from the cache if it's recent enough
+    pub fn packs(&self, source: &PacklistSource) -> Result<Vec<iroffer::Pack>> {
+        let cached = match self.refresh {
+            true => None,
+            false => self
+                .cache
+                .as_ref()
+                .and_then(|c| c.get(&source.url, self.ttl)),
+        };
+        if let Some(list) = cached {
+            return Ok(iroffer::parse_any(&list));
+        }
+
+        let response = self.client.get(&source.url).send()?.error_for_status()?;
+        let list = response.text()?;
+        let packs = iroffer::parse_any(&list);
+        // A page that isn't a packlist, like a login wall, shouldn't stick around
+        if let (Some(cache), false) = (&self.cache, packs.is_empty()) {
+            cache.put(&source.url, &list);
+        }
+        Ok(packs)
+    }
+}
+
+impl Finder for Packlist {
+    /// Sources that fail only produce a warning, unless all of them fail
+    fn find(&self, query: &Query) -> Result<FindResult> {
+        let mut entries = Vec::new();
+        let mut warnings = Vec::new();
+        let mut notes = Vec::new();
+        let mut first_error = None;
+        for source in &self.sources {
+            let packs = match self.packs(source) {
+                Ok(packs) => packs,
+                Err(e) => {
+                    warnings.push(format!("Couldn't get {}: {}", source.url, e));
+                    first_error.get_or_insert(e);
+                    continue;
+                }
+            };
+            let (found, why) = iroffer::search(query, "packlist", &source.address, packs);
+            entries.extend(found);
+            notes.extend(why);
+        }
+
+        match first_error {
+            Some(e) if warnings.len() == self.sources.len() => Err(e),
+            _ => Ok(FindResult {
+                entries,
+                warnings,
+                notes,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::finder::EpisodeNumber;
+    use std::{
+        net::TcpListener,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    #[test]
+    fn dead_sources_only_warn() {
+        let live = crate::finder::stand_in(|_| {
+            "** 2 packs **\n#1  5x [1.4G] [G] Show - 01 (1080p).mkv\n".to_owned()
+        });
+        // Nothing listens on a port that was just freed
+        let dead = {
+            let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", closed.local_addr().unwrap())
+        };
+        let source = |url: &str| {
+            format!("{}/p.txt=Bot@127.0.0.1/#chan", url)
+                .parse()
+                .unwrap()
+        };
+        let query = Query::new("Show".into(), None, EpisodeNumber::All);
+
+        let result = Packlist::new(vec![source(&dead), source(&live)])
+            .find(&query)
+            .unwrap();
+        assert_eq!(result.entries.len(), 1);
+        assert_eq!(result.entries[0].package_number, 1);
+        assert_eq!(result.warnings.len(), 1);
+        assert!(result.warnings[0].starts_with(&format!("Couldn't get {}/p.txt", dead)));
+
+        assert!(Packlist::new(vec![source(&dead)]).find(&query).is_err());
+    }
+
+    #[test]
+    fn reuses_cached_packlists() {
+        // The packlist grows by a pack every time it's downloaded
+        static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+        let url = crate::finder::stand_in(|_| {
+            (0..=REQUESTS.fetch_add(1, Ordering::SeqCst))
+                .map(|n| format!("#{}  5x [1.4G] [G] Show - 0{} (1080p).mkv\n", n + 1, n + 1))
+                .collect()
+        });
+        let source: PacklistSource = format!("{}/p.txt=Bot@127.0.0.1/#chan", url)
+            .parse()
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("mahou-packlists-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+
+        let packlist = Packlist::new(vec![source.clone()]).with_cache(Cache::open_in(&dir));
+        assert_eq!(packlist.packs(&source).unwrap().len(), 1);
+        assert_eq!(packlist.packs(&source).unwrap().len(), 1);
+        let packlist = packlist.with_refresh(true);
+        assert_eq!(packlist.packs(&source).unwrap().len(), 2);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,224 @@
+//! Finds packs by asking bots for their `XDCC LIST` over IRC, for bots that
+//! aren't indexed by any website.
+
+use super::{iroffer, Error, FindResult, Finder, Query, Result};
+use crate::{
+    cache::Cache,
+    downloader::{self, irc},
+};
+use std::{
+    io::Read,
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+/// How long a bot's pack list is reused before asking for it again
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60 * 6);
+
+/// How long to wait for a bot to finish sending its list
+const LIST_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Bots usually send their list as a burst of notices, so this much silence
+/// means the list is over
+const QUIET_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// Lists sent over DCC that claim to be bigger than this aren't downloaded
+const MAX_LIST_SIZE: usize = 16 * 1024 * 1024;
+
+pub struct XdccList {
+    bots: Vec<irc::BotAddress>,
+    cache: Option<Cache>,
+    ttl: Duration,
+    refresh: bool,
+}
+
+impl XdccList {
+    pub fn new(bots: Vec<irc::BotAddress>) -> Self {
+        Self {
+            bots,
+            cache: None,
+            ttl: DEFAULT_TTL,
+            refresh: false,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Caches pack lists in `cache`, which nothing is by default
+    pub fn with_cache(mut self, cache: Option<Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Asks bots for their lists again, even if they're cached
+    pub fn with_refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Limits how much disk space cached pack lists can take
+    pub fn with_cache_size(mut self, max_size: u64) -> Self {
+        self.cache = self.cache.map(|cache| cache.with_max_size(max_size));
+        self
+    }
+
+    /// The packs offered by a bot, from the cache if it's recent enough
+    pub fn packs(&self, address: &irc::BotAddress) -> Result<Vec<iroffer::Pack>> {
+        let key = format!("{}@{}", address.bot, address.config.server).to_lowercase();
+        let cached = match self.refresh {
+            true => None,
+            false => self.cache.as_ref().and_then(|c| c.get(&key, self.ttl)),
+        };
+        if let Some(list) = cached {
+            return Ok(iroffer::parse_any(&list));
+        }
+
+        let list = request_list(address)?;
+        let packs = iroffer::parse_any(&list);
+        // Whatever the bot said instead of a list shouldn't stick around
+        if let (Some(cache), false) = (&self.cache, packs.is_empty()) {
+            cache.put(&key, &list);
+        }
+        Ok(packs)
+    }
+}
+
+impl Finder for XdccList {
+    /// Sources that fail only produce a warning, unless all of them fail
+    fn find(&self, query: &Query) -> Result<FindResult> {
+        let mut entries = Vec::new();
+        let mut warnings = Vec::new();
+        let mut notes = Vec::new();
+        let mut first_error = None;
+        for address in &self.bots {
+            let packs = match self.packs(address) {
+                Ok(packs) => packs,
+                Err(e) => {
+                    warnings.push(format!("Couldn't get {}'s list: {}", address.bot, e));
+                    first_error.get_or_insert(e);
+                    continue;
+                }
+            };
+            let (found, why) = iroffer::search(query, "xdcc list", address, packs);
+            entries.extend(found);
+            notes.extend(why);
+        }
+
+        match first_error {
+            Some(e) if warnings.len() == self.bots.len() => Err(e),
+            _ => Ok(FindResult {
+                entries,
+                warnings,
+                notes,
+            }),
+        }
+    }
+}
+
+/// Asks a bot for its pack list, which is either sent as a bunch of notices
+/// or as a text file over DCC
+fn request_list(address: &irc::BotAddress) -> Result<String> {
+    let mut link = downloader::Link::open(&address.config, Duration::from_secs(1))?;
+    let channel = irc::channel_name(&address.config.channel);
+    let started = Instant::now();
+    let mut last_line: Option<Instant> = None;
+    let mut has_joined = false;
+    let mut has_asked = false;
+    let mut lines = Vec::new();
+
+    loop {
+        if started.elapsed() > LIST_TIMEOUT {
+            break;
+        }
+        if matches!(last_line, Some(t) if t.elapsed() > QUIET_TIMEOUT) {
+            break;
+        }
+
+        // Channels can only be joined once the server has pinged us, and
+        // bots only answer people in their channel
+        if link.registered && !has_joined {
+            link.connection.send(&format!("JOIN {}", channel))?;
+            has_joined = true;
+        }
+        if link.joined_channels.contains(&channel.to_lowercase()) && !has_asked {
+            link.connection
+                .send(&format!("PRIVMSG {} :xdcc list", address.bot))?;
+            has_asked = true;
+        }
+
+        let message = match link.connection.next_message()? {
+            Some(message) => message,
+            None => continue,
+        };
+        // PINGs and our own JOINs
+        if link.handle(&message)? {
+            continue;
+        }
+
+        let from_bot =
+            irc::sender(&message).is_some_and(|sender| sender.eq_ignore_ascii_case(&address.bot));
+        if !from_bot {
+            continue;
+        }
+        if let Some(send) = downloader::parse_dcc_send(&message) {
+            let list = receive_file(send)?;
+            link.connection.quit()?;
+            return Ok(list);
+        }
+        if let Some(captures) = irc::MESSAGE_REGEX.captures(&message) {
+            let line = iroffer::strip_formatting(&captures[4]);
+            let is_footer = line.contains("Total Offered");
+            lines.push(line);
+            last_line = Some(Instant::now());
+            if is_footer {
+                break;
+            }
+        }
+    }
+
+    link.connection.quit()?;
+    if lines.is_empty() {
+        return Err(Error::APIError {
+            api: "xdcc list",
+            message: format!("{} didn't send a pack list", address.bot),
+        });
+    }
+    Ok(lines.join("\n"))
+}
+
+fn receive_file(send: irc::DCCSend) -> Result<String> {
+    if send.file_size > MAX_LIST_SIZE {
+        return Err(Error::APIError {
+            api: "xdcc list",
+            message: format!(
+                "{} is too big to be a pack list ({} bytes)",
+                send.filename, send.file_size
+            ),
+        });
+    }
+
+    // parse_dcc_send already checked the port
+    let address = SocketAddr::new(send.ip, send.port.parse().unwrap_or_default());
+    let mut stream = TcpStream::connect_timeout(&address, QUIET_TIMEOUT)
+        .map_err(downloader::Error::Connection)?;
+    stream
+        .set_read_timeout(Some(QUIET_TIMEOUT))
+        .map_err(downloader::Error::Connection)?;
+
+    let started = Instant::now();
+    let mut contents = Vec::with_capacity(send.file_size);
+    let mut buffer = [0; 8192];
+    while contents.len() < send.file_size && started.elapsed() < LIST_TIMEOUT {
+        let count = stream
+            .read(&mut buffer[..])
+            .map_err(downloader::Error::Connection)?;
+        if count == 0 {
+            break;
+        }
+        contents.extend_from_slice(&buffer[..count]);
+    }
+    Ok(String::from_utf8_lossy(&contents).into_owned())
+}
@@ -0,0 +1,254 @@
+//! Every file mahou has downloaded, so that it can warn before downloading
+//! the same release twice.
+//!
+//! The history is an append-only file in mahou's data directory, with one
+//! JSON record per line.
+
+use crate::{downloader::Completed, release::ReleaseInfo};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+pub const CSV_HEADER: &str = "downloaded_at,name,bot,server,channel,size,crc,duration,path";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// As the bot sent it
+    pub name: String,
+    /// What the finder called the release, which may be different from the
+    /// name of the file, like when the bot truncates it. Older records don't
+    /// have it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry_name: Option<String>,
+    pub bot: String,
+    pub server: String,
+    pub channel: String,
+    /// In bytes
+    pub size: u64,
+    /// CRC32, uppercase
+    pub crc: String,
+    /// How long the transfer took, in seconds
+    pub duration: f64,
+    pub path: PathBuf,
+    pub downloaded_at: DateTime<Local>,
+}
+
+impl Record {
+    pub fn to_csv(&self) -> String {
+        let field = |value: &str| match value.contains([',', '"', '\n']) {
+            true => format!("\"{}\"", value.replace('"', "\"\"")),
+            false => value.to_owned(),
+        };
+        [
+            self.downloaded_at.to_rfc3339(),
+            field(&self.name),
+            field(&self.bot),
+            field(&self.server),
+            field(&self.channel),
+            self.size.to_string(),
+            self.crc.clone(),
+            format!("{:.1}", self.duration),
+            field(&self.path.to_string_lossy()),
+        ]
+        .join(",")
+    }
+}
+
+impl From<&Completed> for Record {
+    fn from(completed: &Completed) -> Self {
+        Self {
+            name: completed.filename.clone(),
+            entry_name: None,
+            bot: completed.bot.clone(),
+            server: completed.server.clone(),
+            channel: completed.channel.clone(),
+            size: completed.size,
+            crc: completed.crc.clone(),
+            duration: completed.duration.as_secs_f64(),
+            path: completed.path.clone(),
+            downloaded_at: Local::now(),
+        }
+    }
+}
+
+/// Every download, oldest first
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct History {
+    pub records: Vec<Record>,
+    /// Why lines of the file were skipped, like when mahou crashed while
+    /// writing one
+    pub warnings: Vec<String>,
+}
+
+impl History {
+    /// Where the history is kept, if there's a data directory on this system
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::data_dir()?.join("mahou").join("history.jsonl"))
+    }
+
+    pub fn load() -> Result<Self, String> {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("Couldn't read {}: {}", path.display(), e)),
+        };
+        Ok(Self::parse(&contents, &path.display().to_string()))
+    }
+
+    /// Parses the lines of a history file, skipping the ones that aren't
+    /// records. `path` is for the warnings.
+    fn parse(contents: &str, path: &str) -> Self {
+        let mut history = Self::default();
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(record) => history.records.push(record),
+                Err(e) => {
+                    history
+                        .warnings
+                        .push(format!("Skipped line {} of {}: {}", i + 1, path, e))
+                }
+            }
+        }
+        history
+    }
+
+    /// Adds a record to the end of the history file
+    pub fn append(record: &Record) -> Result<(), String> {
+        let path = Self::path().ok_or("There's no data directory to keep the history in")?;
+        let write = || {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut line = serde_json::to_string(record)?;
+            line.push('\n');
+            // A single write, so that lines from several downloads don't mix
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)?
+                .write_all(line.as_bytes())
+        };
+        write().map_err(|e: io::Error| format!("Couldn't write to {}: {}", path.display(), e))
+    }
+
+    /// The latest download of a release with this name, either as a finder
+    /// or the bot named it. Names with a CRC also match a file with that CRC.
+    pub fn find(&self, name: &str) -> Option<&Record> {
+        let crc = ReleaseInfo::parse(name).crc;
+        let name = normalize(name);
+        self.records.iter().rev().find(|record| {
+            normalize(&record.name) == name
+                || record
+                    .entry_name
+                    .as_deref()
+                    .is_some_and(|entry_name| normalize(entry_name) == name)
+                || crc
+                    .as_deref()
+                    .is_some_and(|crc| crc.eq_ignore_ascii_case(&record.crc))
+        })
+    }
+}
+
+/// Some bots send files with underscores instead of spaces
+fn normalize(name: &str) -> String {
+    name.replace('_', " ").to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str) -> Record {
+        Record {
+            name: name.to_owned(),
+            entry_name: None,
+            bot: "Bot".to_owned(),
+            server: "irc.rizon.net:6667".to_owned(),
+            channel: "#nibl".to_owned(),
+            size: 1024,
+            crc: "F02B9CEE".to_owned(),
+            duration: 12.34,
+            path: PathBuf::from("/anime/Show - 01.mkv"),
+            downloaded_at: DateTime::parse_from_rfc3339("2023-05-01T12:34:56+00:00")
+                .unwrap()
+                .with_timezone(&Local),
+        }
+    }
+
+    #[test]
+    fn csv_quotes_fields() {
+        let plain = record("[G] Show - 01.mkv").to_csv();
+        assert!(plain.ends_with(
+            ",[G] Show - 01.mkv,Bot,irc.rizon.net:6667,#nibl,1024,F02B9CEE,12.3,/anime/Show - 01.mkv"
+        ));
+        let quoted = record("Show, \"the\" Movie.mkv").to_csv();
+        assert!(quoted.contains(",\"Show, \"\"the\"\" Movie.mkv\",Bot,"));
+        assert_eq!(
+            CSV_HEADER.split(',').count(),
+            plain.split(',').count(),
+            "{}",
+            plain
+        );
+    }
+
+    #[test]
+    fn finds_names_loosely() {
+        let history = History {
+            records: vec![record("[G] Show - 01.mkv"), record("[G]_Show_-_02.mkv")],
+            warnings: Vec::new(),
+        };
+        assert!(history.find("[g] show - 01.MKV").is_some());
+        assert!(history.find("[G]_Show_-_01.mkv").is_some());
+        assert!(history.find("[G] Show - 02.mkv").is_some());
+        assert!(history.find("[G] Show - 03.mkv").is_none());
+    }
+
+    #[test]
+    fn finds_what_finders_called_it() {
+        let mut truncated = record("[G] Show - 01 (1080p) [0000.mkv");
+        truncated.entry_name = Some("[G] Show - 01 (1080p) [00000000].mkv".to_owned());
+        truncated.crc = "11111111".to_owned();
+        let mut renamed = record("show01.MKV");
+        renamed.crc = "ABCD1234".to_owned();
+        let history = History {
+            records: vec![truncated, renamed],
+            warnings: Vec::new(),
+        };
+        assert!(history
+            .find("[G] Show - 01 (1080p) [00000000].mkv")
+            .is_some());
+        assert!(history
+            .find("[G] Show - 02 (1080p) [abcd1234].mkv")
+            .is_some());
+        assert!(history
+            .find("[G] Show - 02 (1080p) [DCBA4321].mkv")
+            .is_none());
+
+        // Records from before entry names were kept
+        let line = r##"{"name":"a.mkv","bot":"Bot","server":"s","channel":"#c","size":1,"crc":"F02B9CEE","duration":1.0,"path":"a.mkv","downloaded_at":"2023-05-01T12:34:56+00:00"}"##;
+        let history = History::parse(line, "history.jsonl");
+        assert_eq!(history.records[0].entry_name, None);
+        assert!(history.warnings.is_empty());
+    }
+
+    #[test]
+    fn skips_broken_lines() {
+        let line = serde_json::to_string(&record("[G] Show - 01.mkv")).unwrap();
+        let contents = format!("{}\n\n{{\"name\": \"[G] Show - 02\n{}\n", line, line);
+        let history = History::parse(&contents, "history.jsonl");
+        assert_eq!(history.records.len(), 2);
+        assert_eq!(history.warnings.len(), 1);
+        assert!(history.warnings[0].starts_with("Skipped line 3 of history.jsonl"));
+    }
+}
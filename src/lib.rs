@@ -1,5 +1,14 @@
 #![doc = include_str!("../README.md")]
 
+pub mod autocompleter;
+pub mod cache;
+pub mod daemon;
 pub mod downloader;
+pub mod filter;
 pub mod finder;
-pub mod autocompleter;
+pub mod history;
+pub mod library;
+pub mod ranking;
+pub mod release;
+pub mod seasons;
+pub mod subscriptions;
@@ -0,0 +1,365 @@
+//! Media-library folder layouts, so downloads can land straight into a
+//! Jellyfin/Plex-friendly structure instead of a flat directory.
+
+use crate::release::ReleaseInfo;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// A destination path template such as
+/// `{show}/Season {season:02}/{show} - S{season:02}E{episode:02} [{group}].{ext}`.
+///
+/// Available fields are `show`, `season`, `episode`, `group`, `resolution`,
+/// `ext` and `name` (the original file name, without extension). Numeric
+/// fields accept a zero-padded width, like `{episode:02}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field { name: String, width: usize },
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_owned()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|i| start + i)
+                .ok_or_else(|| format!("Unclosed '{{' in template {}", s))?;
+
+            let field = &rest[start + 1..end];
+            let (name, width) = match field.split_once(':') {
+                Some((name, width)) => (
+                    name,
+                    width
+                        .parse::<usize>()
+                        .map_err(|_| format!("Invalid width '{}' in template {}", width, s))?,
+                ),
+                None => (field, 0),
+            };
+            if !matches!(
+                name,
+                "show" | "season" | "episode" | "group" | "resolution" | "ext" | "name"
+            ) {
+                return Err(format!("Unknown field '{}' in template {}", name, s));
+            }
+            parts.push(Part::Field {
+                name: name.to_owned(),
+                width,
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_owned()));
+        }
+
+        Ok(Self {
+            source: s.to_owned(),
+            parts,
+        })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl Template {
+    /// Renders the template for a file name, or returns `None` if the name is
+    /// missing some field the template needs. A missing season defaults to 1.
+    pub fn render(&self, filename: &str) -> Option<PathBuf> {
+        let info = ReleaseInfo::parse(filename);
+        let filename = filename.trim();
+        let stem = match &info.extension {
+            Some(_) => filename.rsplit_once('.').map_or(filename, |(stem, _)| stem),
+            None => filename,
+        };
+
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => rendered.push_str(s),
+                Part::Field { name, width } => {
+                    let value = match name.as_str() {
+                        "show" => info.title.clone()?,
+                        "season" => format!("{:0w$}", info.season.unwrap_or(1), w = width),
+                        "episode" => format!("{:0w$}", info.episode?, w = width),
+                        "group" => info.group.clone()?,
+                        "resolution" => info.resolution.clone()?,
+                        "ext" => info.extension.clone()?,
+                        "name" => stem.to_owned(),
+                        _ => unreachable!(),
+                    };
+                    // A `/` in a show or group name isn't a subdirectory
+                    rendered.push_str(&sanitize(&value));
+                }
+            }
+        }
+
+        // Every component is sanitized separately, so the template can't
+        // escape the library either
+        Some(rendered.split('/').map(sanitize).collect())
+    }
+}
+
+/// A single path component made safe to save, without separators or `..`
+fn sanitize(component: &str) -> String {
+    let cleaned: String = component
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    match cleaned.trim() {
+        "" | "." | ".." => "_".to_owned(),
+        s => s.to_owned(),
+    }
+}
+
+/// What to do when a file already exists at the destination
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Collision {
+    /// Don't download the file again
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Save as `name (1).ext`, `name (2).ext`, ...
+    #[default]
+    Rename,
+}
+
+impl FromStr for Collision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "overwrite" => Ok(Self::Overwrite),
+            "rename" => Ok(Self::Rename),
+            _ => Err(format!(
+                "Invalid collision policy {} (expected skip, overwrite or rename)",
+                s
+            )),
+        }
+    }
+}
+
+/// Where downloaded files should be saved
+#[derive(Debug, Clone, Default)]
+pub struct Destination {
+    pub directory: PathBuf,
+    pub template: Option<Template>,
+    pub on_collision: Collision,
+}
+
+impl Destination {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            ..Default::default()
+        }
+    }
+
+    /// The path a file would be saved to, ignoring collisions. Names the
+    /// template can't be rendered for are saved directly in the directory.
+    ///
+    /// File names come from bots, so only the last component of a name like
+    /// `../../.bashrc` is used.
+    pub fn path_for(&self, filename: &str) -> PathBuf {
+        let filename = basename(filename);
+        let relative = self
+            .template
+            .as_ref()
+            .and_then(|t| t.render(filename))
+            .unwrap_or_else(|| PathBuf::from(sanitize(filename)));
+        self.directory.join(relative)
+    }
+
+    /// The path a file should be saved to, after applying the collision
+    /// policy. Returns `None` if the file should be skipped.
+    pub fn resolve(&self, filename: &str) -> Option<PathBuf> {
+        let path = self.path_for(filename);
+        if !path.exists() {
+            return Some(path);
+        }
+
+        match self.on_collision {
+            Collision::Skip => None,
+            Collision::Overwrite => Some(path),
+            Collision::Rename => Some(
+                (1..)
+                    .map(|i| numbered(&path, i))
+                    .find(|p| !p.exists())
+                    .unwrap(),
+            ),
+        }
+    }
+
+    /// Where a file would be saved, as `--dry-run` prints it, without
+    /// downloading or writing anything
+    pub fn plan(&self, filename: &str) -> String {
+        let path = self.path_for(filename);
+        match self.resolve(filename) {
+            Some(resolved) if resolved != path => format!(
+                "{} -> {} ({} already exists)",
+                filename,
+                resolved.display(),
+                path.display()
+            ),
+            Some(resolved) => format!("{} -> {}", filename, resolved.display()),
+            None => format!(
+                "{} -> {} (already exists, would be skipped)",
+                filename,
+                path.display()
+            ),
+        }
+    }
+}
+
+/// The last component of a file name, whichever separator it uses
+fn basename(filename: &str) -> &str {
+    filename.rsplit(['/', '\\']).next().unwrap_or(filename)
+}
+
+fn numbered(path: &Path, i: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, i, ext.to_string_lossy()),
+        None => format!("{} ({})", stem, i),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_stay_in_the_directory() {
+        let mut destination = Destination::new("/library");
+        for filename in ["../../.bashrc", "/etc/passwd", "..\\x.mkv", ".."] {
+            let path = destination.path_for(filename);
+            assert_eq!(path.parent(), Some(Path::new("/library")), "{}", filename);
+        }
+
+        let template: Template = "{group}/{show} - {episode:02}.{ext}".parse().unwrap();
+        assert_eq!(
+            template.render("[A/B] Fate/Zero - 03 [1080p].mkv"),
+            Some(PathBuf::from("A_B/Fate_Zero - 03.mkv"))
+        );
+        assert_eq!(
+            "../{show}.{ext}"
+                .parse::<Template>()
+                .unwrap()
+                .render("Show - 01.mkv"),
+            Some(PathBuf::from("_/Show.mkv"))
+        );
+
+        destination.template = Some(template);
+        assert_eq!(
+            destination.path_for("../[Group] Show - 01.mkv"),
+            Path::new("/library/Group/Show - 01.mkv")
+        );
+    }
+
+    #[test]
+    fn names_keep_their_stem() {
+        let template: Template = "{name} [{episode:02}].{ext}".parse().unwrap();
+        assert_eq!(
+            template.render("  [G] Show - 01 (1080p).MKV  "),
+            Some(PathBuf::from("[G] Show - 01 (1080p) [01].mkv"))
+        );
+        assert_eq!(
+            template.render("[G] Shōjo - 02 ★.mkv"),
+            Some(PathBuf::from("[G] Shōjo - 02 ★ [02].mkv"))
+        );
+    }
+
+    fn library(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("mahou-library-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Show - 01.mkv"), "").unwrap();
+        std::fs::write(dir.join("Show - 01 (1).mkv"), "").unwrap();
+        dir
+    }
+
+    #[test]
+    fn collisions() {
+        let dir = library("collisions");
+        let mut destination = Destination::new(&dir);
+
+        destination.on_collision = Collision::Skip;
+        assert_eq!(destination.resolve("Show - 01.mkv"), None);
+        assert_eq!(
+            destination.resolve("Show - 02.mkv"),
+            Some(dir.join("Show - 02.mkv"))
+        );
+
+        destination.on_collision = Collision::Overwrite;
+        assert_eq!(
+            destination.resolve("Show - 01.mkv"),
+            Some(dir.join("Show - 01.mkv"))
+        );
+
+        // "Show - 01 (1).mkv" is taken too
+        destination.on_collision = Collision::Rename;
+        assert_eq!(
+            destination.resolve("Show - 01.mkv"),
+            Some(dir.join("Show - 01 (2).mkv"))
+        );
+        assert_eq!(numbered(Path::new("/a/Show"), 3), Path::new("/a/Show (3)"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn plans_without_writing() {
+        let dir = library("plans");
+        let mut destination = Destination::new(&dir);
+        let files = || std::fs::read_dir(&dir).unwrap().count();
+
+        assert_eq!(
+            destination.plan("Show - 01.mkv"),
+            format!(
+                "Show - 01.mkv -> {} ({} already exists)",
+                dir.join("Show - 01 (2).mkv").display(),
+                dir.join("Show - 01.mkv").display()
+            )
+        );
+        assert_eq!(
+            destination.plan("Show - 02.mkv"),
+            format!("Show - 02.mkv -> {}", dir.join("Show - 02.mkv").display())
+        );
+        destination.on_collision = Collision::Skip;
+        assert_eq!(
+            destination.plan("Show - 01.mkv"),
+            format!(
+                "Show - 01.mkv -> {} (already exists, would be skipped)",
+                dir.join("Show - 01.mkv").display()
+            )
+        );
+        assert_eq!(files(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,22 +1,51 @@
 use argh::FromArgs;
 use mahou::{
     autocompleter::{Autocompleter, EntrySet},
-    downloader,
+    cache::{self, Cache},
+    daemon::{self, parse_interval, Lock},
+    downloader::{self, irc::BotAddress, parse_limit, BotLimit, Limits, Pool, Session, Stream},
+    filter::{FeedFilter, Filter},
     finder::{self, EpisodeNumber},
+    history::{self, History, Record},
+    library::{Collision, Destination, Template},
+    ranking::{self, Preferences},
+    seasons::SeasonMap,
+    subscriptions::{Subscription, Subscriptions},
+};
+use owo_colors::OwoColorize;
+use std::{
+    cell::RefCell,
+    error::Error,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Once,
+    },
+    thread,
+    time::{Duration, Instant},
 };
-use std::error::Error;
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
+/// How long the daemon sleeps at a time while it has no connections to keep
+/// alive, or while waiting for the next sync, before checking if it should stop
+const DAEMON_IDLE_SLEEP: Duration = Duration::from_secs(1);
+
 /// Mahou -- magically easy anime downloader.
 /// If --search or --episode are missing, mahou will interactively prompt for them.
 #[derive(Debug, FromArgs)]
 struct Args {
+    #[argh(subcommand)]
+    command: Option<Command>,
+
     /// the show you want to search for
     #[argh(option, short = 's')]
     search: Option<String>,
 
-    /// the episode to download
+    /// the episode to download: a number, a range like 1-12 or 10-, a list
+    /// like 3,5,7, a season like S02E03, 2x03, S02E01-06 or S02, 'latest',
+    /// 'latest-1' or 'all'
     #[argh(option, short = 'e')]
     episode: Option<EpisodeNumber>,
 
@@ -24,17 +53,238 @@ struct Args {
     #[argh(option, short = 'd', default = "\"./\".to_string()")]
     directory: String,
 
+    /// path template to organise downloads into subdirectories (see the README)
+    #[argh(option, short = 't')]
+    template: Option<Template>,
+
+    /// what to do when the file already exists: skip, overwrite or rename (default)
+    #[argh(option, default = "Collision::default()")]
+    on_collision: Collision,
+
+    /// show where the file would be saved, without downloading it
+    #[argh(switch)]
+    dry_run: bool,
+
+    /// also send the file somewhere while it downloads, to watch it right
+    /// away: 'stdout', or 'http' or 'http:PORT' to serve it on localhost.
+    /// Without --play, the file stays up until something has played it, or
+    /// for 10 minutes after it's downloaded
+    #[argh(option)]
+    stream: Option<Stream>,
+
+    /// watch the episode in a player while it downloads (see --player)
+    #[argh(switch)]
+    play: bool,
+
+    /// the player for --play, along with any arguments. Defaults to mpv
+    #[argh(option, default = "\"mpv\".to_string()")]
+    player: String,
+
     /// preferred resolution.
     #[argh(option, short = 'r')]
     res: Option<String>,
 
-    /// filter for the results
+    /// only releases by this group. With --episode latest, that's the group's
+    /// latest episode, even if other groups are ahead
+    #[argh(option, short = 'g')]
+    group: Option<String>,
+
+    /// filter for the results, like: SubsPlease|Erai-raws -720p size:<2G ep:>=5
+    /// (see the README for everything it can do)
     #[argh(option, short = 'f')]
-    filter: Option<String>,
+    filter: Option<Filter>,
+
+    /// also search the XDCC LIST of a bot, given as bot@server:port/#channel.
+    /// Can be repeated
+    #[argh(option)]
+    xdcc_list: Vec<BotAddress>,
+
+    /// also search a packlist published on the web, given as
+    /// URL=bot@server:port/#channel. Can be repeated
+    #[argh(option)]
+    packlist: Vec<finder::PacklistSource>,
+
+    /// use a mirror of Nibl's API at this URL
+    #[argh(option)]
+    nibl_url: Option<String>,
 
-    /// download the first result instead of prompting to pick one
+    /// don't also search for the show's other names
+    #[argh(switch)]
+    no_aliases: bool,
+
+    /// look up the show's other names on AniList
+    #[argh(switch)]
+    anilist: bool,
+
+    /// don't search Nibl, only the sources given by --xdcc-list and --packlist
+    #[argh(switch)]
+    no_nibl: bool,
+
+    /// explain how results were picked, like which episode is the latest and
+    /// how each entry was scored
+    #[argh(switch, short = 'v')]
+    verbose: bool,
+
+    /// ignore cached search results, bot lists and pack lists
+    #[argh(switch)]
+    refresh: bool,
+
+    /// how much disk space each cache (Nibl, pack lists, AniList) can take,
+    /// like 64M. Defaults to 32M
+    #[argh(
+        option,
+        from_str_fn(parse_cache_size),
+        default = "cache::DEFAULT_MAX_SIZE"
+    )]
+    cache_size: u64,
+
+    /// delete everything mahou has cached
+    #[argh(switch)]
+    clear_cache: bool,
+
+    /// download the best result instead of prompting to pick one; releases
+    /// that are in the history are skipped unless --redownload is given
     #[argh(switch)]
     download_first: bool,
+
+    /// download releases that are in the history again without asking
+    #[argh(switch)]
+    redownload: bool,
+
+    /// maximum number of files to download at the same time
+    #[argh(option, from_str_fn(parse_limit))]
+    max_transfers: Option<usize>,
+
+    /// maximum number of files to download from the same bot at the same time
+    #[argh(option, from_str_fn(parse_limit))]
+    max_transfers_per_bot: Option<usize>,
+
+    /// per-bot transfer limit as BOT=N, overriding --max-transfers-per-bot.
+    /// Can be repeated
+    #[argh(option)]
+    bot_limit: Vec<BotLimit>,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Latest(LatestArgs),
+    Subscribe(SubscribeArgs),
+    Unsubscribe(UnsubscribeArgs),
+    Subscriptions(SubscriptionsArgs),
+    Sync(SyncArgs),
+    History(HistoryArgs),
+    Daemon(DaemonArgs),
+}
+
+/// Browse the latest releases on Nibl and pick some to download
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "latest")]
+struct LatestArgs {
+    /// only show releases in this resolution
+    #[argh(option, short = 'r')]
+    res: Option<String>,
+
+    /// only show releases by this group
+    #[argh(option, short = 'g')]
+    group: Option<String>,
+
+    /// how many of the latest packs to look at. Defaults to 100
+    #[argh(option, default = "100")]
+    count: usize,
+}
+
+/// Follow a show, so that `mahou sync` downloads its new episodes
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "subscribe")]
+struct SubscribeArgs {
+    /// the show to follow
+    #[argh(positional)]
+    show: String,
+
+    /// only download releases in this resolution
+    #[argh(option, short = 'r')]
+    res: Option<String>,
+
+    /// only download releases by this group
+    #[argh(option, short = 'g')]
+    group: Option<String>,
+
+    /// only download releases matching this filter (see the README)
+    #[argh(option, short = 'f')]
+    filter: Option<String>,
+
+    /// ignore episodes before this one
+    #[argh(option)]
+    from: Option<i32>,
+}
+
+/// Stop following a show
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "unsubscribe")]
+struct UnsubscribeArgs {
+    /// the show to stop following
+    #[argh(positional)]
+    show: String,
+}
+
+/// List the shows you follow and the episodes downloaded so far
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "subscriptions")]
+struct SubscriptionsArgs {}
+
+/// Download the new episodes of every show you follow
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "sync")]
+struct SyncArgs {}
+
+/// Keep running, syncing subscriptions every so often
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "daemon")]
+struct DaemonArgs {
+    /// minutes between checks, give or take a few. Defaults to 30, and can be
+    /// at most a week
+    #[argh(option, from_str_fn(parse_interval), default = "30")]
+    interval: u64,
+
+    /// also stay in #nibl, and download subscribed episodes as soon as bots
+    /// announce them there
+    #[argh(switch)]
+    listen: bool,
+}
+
+/// List what mahou has downloaded, oldest first
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "history")]
+struct HistoryArgs {
+    /// only list downloads with this in their name
+    #[argh(positional)]
+    search: Option<String>,
+
+    /// how to print the history: text (default), json (one record per line)
+    /// or csv
+    #[argh(option, default = "HistoryFormat::Text")]
+    format: HistoryFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HistoryFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for HistoryFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!("Unknown format {}, expected text, json or csv", s)),
+        }
+    }
 }
 
 fn prompt_search() -> Result<String> {
@@ -52,47 +302,195 @@ fn prompt_search() -> Result<String> {
 fn prompt_episode() -> Result<EpisodeNumber> {
     Ok(inquire::CustomType::<EpisodeNumber>::new("Which episode?")
         .with_default(EpisodeNumber::Latest)
-        .with_help_message("Enter a number, 'latest', or 'all' to show all available episodes")
+        .with_help_message(
+            "Enter a number, a range like 1-12 or 10-, a list like 3,5,7, a season like S02E03, \
+             'latest', 'latest-1', or 'all' to show all available episodes",
+        )
         .prompt()?)
 }
 
-fn filter(words: &str, entry: &str) -> bool {
-    let entry = entry.to_lowercase();
-    let words = words.to_lowercase();
-    words.split_whitespace().all(|word| entry.contains(word))
+/// Filters with whatever the user has typed so far, which may not be a valid
+/// filter yet. The input is only parsed again when it changes.
+#[derive(Default)]
+struct InputFilter {
+    parsed: RefCell<(String, Option<Filter>)>,
 }
 
-fn main() -> Result<()> {
-    let args: Args = argh::from_env();
+impl InputFilter {
+    fn matches(&self, input: &str, entry: &finder::Entry) -> bool {
+        let mut parsed = self.parsed.borrow_mut();
+        if parsed.0 != input {
+            *parsed = (input.to_owned(), input.parse().ok());
+        }
+        match &parsed.1 {
+            Some(filter) => filter.matches(entry),
+            None => true,
+        }
+    }
+}
 
-    let search = match args.search {
-        Some(search) => search,
-        None => prompt_search()?,
-    };
+/// The ranking preferences, or the defaults if they can't be read
+fn load_preferences() -> Preferences {
+    Preferences::load().unwrap_or_else(|e| {
+        eprintln!("{} {}", "warning:".yellow(), e);
+        Preferences::default()
+    })
+}
 
-    let episode = match args.episode {
-        Some(episode) => episode,
-        None => prompt_episode()?,
-    };
+/// Sorts entries from best to worst, explaining the scores if asked to
+fn rank(
+    preferences: &Preferences,
+    entries: Vec<finder::Entry>,
+    verbose: bool,
+) -> Vec<finder::Entry> {
+    let ranked = preferences.rank(entries);
+    if verbose {
+        for (entry, score) in &ranked {
+            eprintln!("{} {} scored {}", "note:".cyan(), entry.name, score);
+        }
+    }
+    ranked.into_iter().map(|(entry, _)| entry).collect()
+}
+
+fn parse_cache_size(size: &str) -> std::result::Result<u64, String> {
+    finder::parse_size(size)
+        .ok_or_else(|| format!("Invalid cache size {} (expected something like 64M)", size))
+}
+
+fn nibl(args: &Args) -> Result<finder::Nibl> {
+    let mut nibl = finder::Nibl::builder();
+    if let Some(url) = &args.nibl_url {
+        nibl = nibl.base_url(url);
+    }
+    Ok(nibl
+        .cache(Cache::open("nibl"))
+        .build()?
+        .with_refresh(args.refresh)
+        .with_cache_size(args.cache_size))
+}
+
+fn load_aliases() -> finder::Aliases {
+    finder::Aliases::load().unwrap_or_else(|e| {
+        eprintln!("{} {}", "warning:".yellow(), e);
+        finder::Aliases::bundled()
+    })
+}
+
+/// Every name releases of `show` could go by
+fn show_names(args: &Args, show: &str) -> Vec<String> {
+    if args.no_aliases {
+        return vec![show.to_owned()];
+    }
+    let mut names = load_aliases().expand(show);
+    if args.anilist {
+        let anilist = finder::AniList::default()
+            .with_cache(Cache::open("anilist"))
+            .with_cache_size(args.cache_size);
+        // Searching already warned if AniList can't be reached
+        names.extend(anilist.names(show).unwrap_or_default());
+    }
+    names
+}
+
+/// The season mappings, or none if they can't be read
+fn load_seasons() -> SeasonMap {
+    SeasonMap::load().unwrap_or_else(|e| {
+        eprintln!("{} {}", "warning:".yellow(), e);
+        SeasonMap::default()
+    })
+}
+
+/// Searches every source for `query`, and returns what's found, filtered and
+/// best first. Returns `None` if nothing was found.
+fn search_entries(args: &Args, query: &finder::Query) -> Result<Option<Vec<finder::Entry>>> {
+    let mut finder = finder::Multi::new();
+    if !args.no_nibl {
+        finder = finder.with("nibl", nibl(args)?);
+    }
+    if !args.xdcc_list.is_empty() {
+        let xdcc_list = finder::XdccList::new(args.xdcc_list.clone())
+            .with_cache(Cache::open("xdcc-lists"))
+            .with_refresh(args.refresh)
+            .with_cache_size(args.cache_size);
+        finder = finder.with("xdcc list", xdcc_list);
+    }
+    if !args.packlist.is_empty() {
+        let packlist = finder::Packlist::new(args.packlist.clone())
+            .with_cache(Cache::open("packlists"))
+            .with_refresh(args.refresh)
+            .with_cache_size(args.cache_size);
+        finder = finder.with("packlist", packlist);
+    }
+
+    if finder.is_empty() {
+        eprintln!("Nothing to search! Use --xdcc-list or --packlist along with --no-nibl");
+        return Ok(None);
+    }
 
-    let results = finder::Query::new(search, args.res, episode).find(&finder::Nibl::default())?;
+    let results = if args.no_aliases {
+        query.find(&finder)?
+    } else {
+        let aliases = load_aliases();
+        let anilist = args.anilist.then(|| {
+            finder::AniList::default()
+                .with_cache(Cache::open("anilist"))
+                .with_refresh(args.refresh)
+                .with_cache_size(args.cache_size)
+        });
+        query.find(&finder::Aliased::new(finder, aliases).with_anilist(anilist))?
+    };
     let finder::FindResult {
-        irc_config,
         mut entries,
+        warnings,
+        notes,
     } = results;
 
-    if let Some(f) = &args.filter {
-        entries.retain(|entry| filter(f, &format!("{}", entry)));
+    if args.verbose {
+        for note in notes {
+            eprintln!("{} {}", "note:".cyan(), note);
+        }
+    }
+
+    for warning in warnings {
+        eprintln!("{} {}", "warning:".yellow(), warning);
+    }
+
+    if let Some(filter) = &args.filter {
+        entries.retain(|entry| filter.matches(entry));
     }
 
     if entries.is_empty() {
         eprintln!("No results found :(");
-        return Ok(());
+        return Ok(None);
     }
 
+    // Best entries first, so --download-first picks them
+    Ok(Some(rank(&load_preferences(), entries, args.verbose)))
+}
+
+/// Searches for a show and lets the user pick what to download. Returns
+/// `None` if there's nothing to download.
+fn pick_search(args: &Args) -> Result<Option<Vec<finder::Entry>>> {
+    let search = match &args.search {
+        Some(search) => search.clone(),
+        None => prompt_search()?,
+    };
+
+    let episode = match &args.episode {
+        Some(episode) => episode.clone(),
+        None => prompt_episode()?,
+    };
+
+    let query = finder::Query::new(search, args.res.clone(), episode.clone())
+        .with_group(args.group.as_deref())
+        .with_seasons(load_seasons());
+    let entries = match search_entries(args, &query)? {
+        Some(entries) => entries,
+        None => return Ok(None),
+    };
+
     let selected = if args.download_first {
-        // Pick first entry
-        entries.swap_remove(0)
+        ranking::pick_best(entries, &query)
     } else {
         // Prompt the user to pick an episode
 
@@ -100,16 +498,645 @@ fn main() -> Result<()> {
         // - Current option being evaluated, with type preserved
         // - String value of the current option
         // - Index of the current option in the original list
-        let inquire_filter = &|input: &str, _: &finder::Entry, entry: &str, _: usize| {
-            filter(input, entry)
+        let input_filter = InputFilter::default();
+        let inquire_filter = &|input: &str, entry: &finder::Entry, _: &str, _: usize| {
+            input_filter.matches(input, entry)
         };
 
-        inquire::Select::new("Pick an episode", entries)
-            .with_filter(inquire_filter)
-            .prompt()?
+        if episode.is_many() {
+            inquire::MultiSelect::new("Pick the episodes to download", entries)
+                .with_filter(inquire_filter)
+                .prompt()?
+        } else {
+            vec![inquire::Select::new("Pick an episode", entries)
+                .with_filter(inquire_filter)
+                .prompt()?]
+        }
     };
 
-    downloader::download(&selected, irc_config, args.directory)?;
+    Ok(Some(selected))
+}
+
+/// An episode among the latest releases, with every release of it, best
+/// first
+struct NewEpisode {
+    title: String,
+    episode: Option<i32>,
+    entries: Vec<finder::Entry>,
+}
 
+impl fmt::Display for NewEpisode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.title.bold())?;
+        if let Some(episode) = self.episode {
+            write!(f, " - {:02}", episode)?;
+        }
+        write!(f, ": {}", self.entries[0])?;
+        if self.entries.len() > 1 {
+            write!(
+                f,
+                " {}",
+                format!("(+{} more)", self.entries.len() - 1).dimmed()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Shows the latest releases on Nibl, grouped by show and episode, and lets
+/// the user pick what to download. Returns `None` if there's nothing to
+/// download.
+fn pick_latest(args: &Args, latest: &LatestArgs) -> Result<Option<Vec<finder::Entry>>> {
+    let feed = FeedFilter {
+        resolution: latest.res.clone(),
+        group: latest.group.clone(),
+        filter: args.filter.clone(),
+    };
+    if args.download_first && !feed.narrows() {
+        return Err(
+            "--download-first with latest needs --res, --group or --filter, so that it doesn't \
+            download everything in the feed"
+                .into(),
+        );
+    }
+
+    let mut entries = nibl(args)?.latest(latest.count)?;
+    entries.retain(|entry| feed.matches(entry));
+
+    // Group by show and episode, newest first
+    let mut episodes: Vec<NewEpisode> = Vec::new();
+    for entry in entries {
+        let release = entry.release();
+        let title = release.title.unwrap_or_else(|| entry.name.clone());
+        let same_episode = |e: &&mut NewEpisode| {
+            e.title.eq_ignore_ascii_case(&title) && e.episode == release.episode
+        };
+        match episodes.iter_mut().find(same_episode) {
+            Some(episode) => episode.entries.push(entry),
+            None => episodes.push(NewEpisode {
+                title,
+                episode: release.episode,
+                entries: vec![entry],
+            }),
+        }
+    }
+    let preferences = load_preferences();
+    for episode in &mut episodes {
+        episode.entries = rank(
+            &preferences,
+            std::mem::take(&mut episode.entries),
+            args.verbose,
+        );
+    }
+
+    if episodes.is_empty() {
+        eprintln!("No results found :(");
+        return Ok(None);
+    }
+
+    if args.download_first {
+        // The best release of everything that's left, newest first if tied
+        let entries = episodes.into_iter().flat_map(|e| e.entries).collect();
+        let ranked = rank(&preferences, entries, false);
+        let query = finder::Query::new(String::new(), None, EpisodeNumber::All);
+        return Ok(Some(ranking::pick_best(ranked, &query)));
+    }
+
+    let input_filter = InputFilter::default();
+    let inquire_filter = &|input: &str, episode: &NewEpisode, _: &str, _: usize| {
+        episode
+            .entries
+            .iter()
+            .any(|entry| input_filter.matches(input, entry))
+    };
+    let selected = inquire::MultiSelect::new("Pick the episodes to download", episodes)
+        .with_filter(inquire_filter)
+        .prompt()?;
+
+    // The best release of each episode
+    Ok(Some(
+        selected
+            .into_iter()
+            .map(|mut episode| episode.entries.swap_remove(0))
+            .collect(),
+    ))
+}
+
+fn load_history() -> History {
+    let history = History::load().unwrap_or_else(|e| {
+        eprintln!("{} {}", "warning:".yellow(), e);
+        History::default()
+    });
+    // The history is loaded again and again while syncing
+    static WARNED: Once = Once::new();
+    WARNED.call_once(|| {
+        for warning in &history.warnings {
+            eprintln!("{} {}", "warning:".yellow(), warning);
+        }
+    });
+    history
+}
+
+/// Warns about entries that were downloaded before, and asks whether to
+/// download them again. With --redownload they're kept without asking, and
+/// otherwise --download-first skips them.
+fn skip_downloaded(args: &Args, selected: &[finder::Entry]) -> Result<Vec<finder::Entry>> {
+    let history = load_history();
+    let mut kept = Vec::new();
+    for entry in selected {
+        if let Some(record) = history.find(&entry.name) {
+            eprintln!(
+                "{} {} was already downloaded on {} to {}",
+                "warning:".yellow(),
+                entry.name,
+                record.downloaded_at.format("%Y-%m-%d %H:%M"),
+                record.path.display()
+            );
+            let again = args.redownload
+                || !args.download_first
+                    && (args.dry_run
+                        || inquire::Confirm::new("Download it again?")
+                            .with_default(false)
+                            .prompt()?);
+            if !again {
+                continue;
+            }
+        }
+        kept.push(entry.clone());
+    }
+    Ok(kept)
+}
+
+/// Where and how to download, from the command line. Completed downloads are
+/// added to the history.
+fn download(args: &Args, selected: &[finder::Entry], pool: Option<&Pool>) -> Result<()> {
+    let selected = skip_downloaded(args, selected)?;
+    if selected.is_empty() {
+        return Ok(());
+    }
+
+    // Players need something they can read at their own pace
+    let stream = match (&args.stream, args.play) {
+        (Some(Stream::Stdout), true) => {
+            return Err("--play can't be used along with --stream stdout".into())
+        }
+        (stream, true) => Some(Stream::Http {
+            port: match stream {
+                Some(Stream::Http { port, .. }) => *port,
+                _ => 0,
+            },
+            player: Some(args.player.split_whitespace().map(String::from).collect()),
+        }),
+        (stream, false) => stream.clone(),
+    };
+    if stream.is_some() && selected.len() > 1 {
+        return Err("--stream and --play only work with one episode at a time".into());
+    }
+
+    let destination = Destination {
+        directory: args.directory.clone().into(),
+        template: args.template.clone(),
+        on_collision: args.on_collision,
+    };
+
+    if args.dry_run {
+        for entry in &selected {
+            println!("{}", destination.plan(&entry.name));
+        }
+        return Ok(());
+    }
+
+    let limits = Limits {
+        total: args.max_transfers,
+        per_bot: args.max_transfers_per_bot,
+        bots: args
+            .bot_limit
+            .iter()
+            .map(|BotLimit { bot, limit }| (bot.clone(), *limit))
+            .collect(),
+    };
+
+    let record = |completed: &downloader::Completed| {
+        let mut record = Record::from(completed);
+        record.entry_name = selected
+            .iter()
+            .find(|entry| {
+                entry.bot_name.eq_ignore_ascii_case(&completed.bot)
+                    && entry.package_number.to_string() == completed.package
+            })
+            .map(|entry| entry.name.clone());
+        if let Err(e) = History::append(&record) {
+            eprintln!("{} {}", "warning:".yellow(), e);
+        }
+    };
+    let mut session = Session::new(&destination, &limits)
+        .on_complete(&record)
+        .with_stream(stream);
+    if let Some(pool) = pool {
+        session = session.with_pool(pool);
+    }
+    for entry in &selected {
+        session.add(entry);
+    }
+    session.run()?;
+    Ok(())
+}
+
+fn list_history(history_args: &HistoryArgs) -> Result<()> {
+    let history = History::load()?;
+    for warning in &history.warnings {
+        eprintln!("{} {}", "warning:".yellow(), warning);
+    }
+    let search = history_args.search.as_deref().map(str::to_lowercase);
+    let records = history.records.iter().filter(|record| match &search {
+        Some(search) => [Some(&record.name), record.entry_name.as_ref()]
+            .into_iter()
+            .flatten()
+            .any(|name| name.to_lowercase().contains(search)),
+        None => true,
+    });
+
+    match history_args.format {
+        HistoryFormat::Text => {
+            for record in records {
+                println!(
+                    "{} {} {} {}",
+                    record.downloaded_at.format("%Y-%m-%d %H:%M").dimmed(),
+                    record.name,
+                    format!("({})", indicatif::HumanBytes(record.size)).yellow(),
+                    format!("from {} -> {}", record.bot, record.path.display()).dimmed()
+                );
+            }
+        }
+        HistoryFormat::Json => {
+            for record in records {
+                println!("{}", serde_json::to_string(record)?);
+            }
+        }
+        HistoryFormat::Csv => {
+            println!("{}", history::CSV_HEADER);
+            for record in records {
+                println!("{}", record.to_csv());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn load_subscriptions() -> Result<Subscriptions> {
+    Ok(Subscriptions::load()?)
+}
+
+fn subscribe(subscribe: &SubscribeArgs) -> Result<()> {
+    if let Some(filter) = &subscribe.filter {
+        filter.parse::<Filter>()?;
+    }
+
+    let mut subscriptions = load_subscriptions()?;
+    subscriptions.add(Subscription {
+        resolution: subscribe.res.clone(),
+        group: subscribe.group.clone(),
+        filter: subscribe.filter.clone(),
+        from: subscribe.from,
+        ..Subscription::new(subscribe.show.clone())
+    });
+    subscriptions.save()?;
+    println!("Subscribed to {}", subscribe.show.bold());
+    Ok(())
+}
+
+fn unsubscribe(unsubscribe: &UnsubscribeArgs) -> Result<()> {
+    let mut subscriptions = load_subscriptions()?;
+    if !subscriptions.remove(&unsubscribe.show) {
+        eprintln!("You're not subscribed to {}", unsubscribe.show);
+        return Ok(());
+    }
+    subscriptions.save()?;
+    println!("Unsubscribed from {}", unsubscribe.show.bold());
+    Ok(())
+}
+
+fn list_subscriptions() -> Result<()> {
+    let subscriptions = load_subscriptions()?;
+    if subscriptions.subscriptions.is_empty() {
+        eprintln!("No subscriptions yet! Add one with: mahou subscribe \"Show\"");
+    }
+    for subscription in &subscriptions.subscriptions {
+        println!("{}", subscription);
+    }
     Ok(())
 }
+
+/// Marks the episodes of `entries` that made it to the history as downloaded,
+/// even if other downloads failed
+fn save_progress<'e>(
+    show: &str,
+    entries: impl IntoIterator<Item = &'e finder::Entry>,
+    history: &History,
+) -> Result<()> {
+    let downloaded: Vec<i32> = entries
+        .into_iter()
+        .filter(|entry| history.find(&entry.name).is_some())
+        .filter_map(|entry| entry.episode)
+        .collect();
+    if downloaded.is_empty() {
+        return Ok(());
+    }
+
+    // Subscriptions may have changed while downloading
+    let mut subscriptions = load_subscriptions()?;
+    if let Some(subscription) = subscriptions.get_mut(show) {
+        subscription.mark_downloaded(downloaded);
+        subscriptions.save()?;
+    }
+    Ok(())
+}
+
+/// Downloads the episodes of each subscription that weren't downloaded yet,
+/// saving progress after each show. Connections are left in `pool`, if
+/// there's one.
+fn sync(args: &Args, pool: Option<&Pool>) -> Result<()> {
+    let subscriptions = load_subscriptions()?;
+    if subscriptions.subscriptions.is_empty() {
+        eprintln!("No subscriptions yet! Add one with: mahou subscribe \"Show\"");
+        return Ok(());
+    }
+
+    let mut history = load_history();
+    let mut failed = 0;
+    for i in 0..subscriptions.subscriptions.len() {
+        let subscription = &subscriptions.subscriptions[i];
+        eprintln!("Checking {}...", subscription.show.bold());
+        let query = subscription.query().with_seasons(load_seasons());
+        let entries = match search_entries(args, &query) {
+            Ok(Some(entries)) => entries,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!(
+                    "{} Couldn't search for {}: {}",
+                    "error:".red(),
+                    subscription.show,
+                    e
+                );
+                failed += 1;
+                continue;
+            }
+        };
+
+        let pending = match subscription.pending(entries, &show_names(args, &subscription.show)) {
+            Ok(pending) => pending,
+            Err(e) => {
+                eprintln!("{} {}", "error:".red(), e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        // Episodes downloaded without sync count too
+        let (previous, pending): (Vec<_>, Vec<_>) = pending
+            .into_iter()
+            .partition(|entry| history.find(&entry.name).is_some());
+
+        let result = match pending.is_empty() {
+            true => {
+                eprintln!("No new episodes");
+                Ok(())
+            }
+            false => download(args, &pending, pool),
+        };
+
+        if !pending.is_empty() {
+            history = load_history();
+        }
+        let show = &subscriptions.subscriptions[i].show;
+        if !args.dry_run {
+            save_progress(show, previous.iter().chain(&pending), &history)?;
+        }
+
+        if let Err(e) = result {
+            eprintln!("{} Couldn't download {}: {}", "error:".red(), show, e);
+            failed += 1;
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!(
+            "{} of {} shows failed to sync",
+            failed,
+            subscriptions.subscriptions.len()
+        )
+        .into()),
+    }
+}
+
+/// Prints a line to stderr with the time, for the daemon's log
+fn log(message: impl fmt::Display) {
+    let now = chrono::Local::now().format("[%Y-%m-%d %H:%M:%S]");
+    eprintln!("{} {}", now.dimmed(), message);
+}
+
+/// Downloads an entry a bot just announced, if a subscription wants it
+fn download_announced(args: &Args, pool: &Pool, entry: finder::Entry) -> Result<()> {
+    if args.filter.as_ref().is_some_and(|f| !f.matches(&entry)) {
+        return Ok(());
+    }
+    if load_history().find(&entry.name).is_some() {
+        return Ok(());
+    }
+
+    let aliases = match args.no_aliases {
+        true => finder::Aliases::default(),
+        false => load_aliases(),
+    };
+    let subscriptions = load_subscriptions()?;
+    let subscription = match subscriptions
+        .subscriptions
+        .iter()
+        .find(|s| s.wants(&entry, &aliases.expand(&s.show)))
+    {
+        Some(subscription) => subscription,
+        None => return Ok(()),
+    };
+
+    log(format!(
+        "{} announced {}",
+        entry.bot_name,
+        entry.name.bold()
+    ));
+    let result = download(args, std::slice::from_ref(&entry), Some(pool));
+    if !args.dry_run {
+        save_progress(&subscription.show, [&entry], &load_history())?;
+    }
+    result
+}
+
+/// Syncs subscriptions every `interval` minutes or so, keeping IRC connections
+/// open in between
+fn run_daemon(args: &Args, daemon_args: &DaemonArgs) -> Result<()> {
+    let _lock = Lock::acquire()?;
+    let interval = Duration::from_secs(daemon_args.interval * 60);
+    let pool = Arc::new(Pool::new());
+    let stop = Arc::new(AtomicBool::new(false));
+    let (closing, stopping) = (pool.clone(), stop.clone());
+    let stopped = ctrlc::set_handler(move || {
+        if !stopping.swap(true, Ordering::Relaxed) {
+            log("Stopping once the current sync is done, press Ctrl-C again to stop now");
+            return;
+        }
+        log("Stopping");
+        closing.close();
+        // Exiting doesn't drop the lock, which would leave our PID behind
+        Lock::clear();
+        std::process::exit(130);
+    });
+    if let Err(e) = stopped {
+        log(format!(
+            "{} Couldn't handle Ctrl-C: {}",
+            "warning:".yellow(),
+            e
+        ));
+    }
+    log(format!(
+        "Started, syncing subscriptions about every {} minutes",
+        interval.as_secs() / 60
+    ));
+    if daemon_args.listen {
+        pool.listen(finder::NIBL_CONFIG.as_ref().clone());
+        log(format!(
+            "Listening to announcements in {}",
+            finder::NIBL_CONFIG.channel
+        ));
+    }
+
+    // Connections are kept alive on their own thread, so that servers get
+    // their PONGs and announcements keep coming while we download
+    let (announcements_tx, announcements) = mpsc::channel();
+    let bots = nibl(args)?.with_refresh(false);
+    let result = thread::scope(|scope| {
+        scope.spawn(|| keep_connections(&pool, &bots, &stop, announcements_tx));
+        let result = sync_forever(args, &pool, interval, &stop, &announcements);
+        stop.store(true, Ordering::Relaxed);
+        result
+    });
+    pool.close();
+    log("Stopped");
+    result
+}
+
+/// Syncs subscriptions every `interval` or so, and downloads what bots
+/// announce in between, until `stop`
+fn sync_forever(
+    args: &Args,
+    pool: &Pool,
+    interval: Duration,
+    stop: &AtomicBool,
+    announcements: &mpsc::Receiver<finder::Entry>,
+) -> Result<()> {
+    while !stop.load(Ordering::Relaxed) {
+        log("Syncing subscriptions");
+        match sync(args, Some(pool)) {
+            Ok(()) => log("Synced"),
+            Err(e) => log(format!("{} {}", "error:".red(), e)),
+        }
+
+        let wait = daemon::jittered(interval);
+        let next = chrono::Local::now() + chrono::Duration::from_std(wait)?;
+        log(format!("Next sync at {}", next.format("%H:%M")));
+
+        let until = Instant::now() + wait;
+        while let Some(left) = until.checked_duration_since(Instant::now()) {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let entry = match announcements.recv_timeout(left.min(DAEMON_IDLE_SLEEP)) {
+                Ok(entry) => entry,
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    thread::sleep(left.min(DAEMON_IDLE_SLEEP));
+                    continue;
+                }
+            };
+            if let Err(e) = download_announced(args, pool, entry) {
+                log(format!("{} {}", "error:".red(), e));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Keeps the connections in `pool` alive until `stop`, sending what Nibl's
+/// bots announce to `announcements`
+fn keep_connections(
+    pool: &Pool,
+    bots: &finder::Nibl,
+    stop: &AtomicBool,
+    announcements: mpsc::Sender<finder::Entry>,
+) {
+    while !stop.load(Ordering::Relaxed) {
+        for (server, e) in pool.reconnect() {
+            log(format!(
+                "{} Couldn't connect to {}: {}",
+                "error:".red(),
+                server,
+                e
+            ));
+        }
+        if pool.is_empty() {
+            thread::sleep(DAEMON_IDLE_SLEEP);
+            continue;
+        }
+        for (server, message) in pool.keep_alive() {
+            let is_bot = |nick: &str| {
+                bots.is_bot(nick).unwrap_or_else(|e| {
+                    log(format!(
+                        "{} Couldn't check if {} is a bot: {}",
+                        "warning:".yellow(),
+                        nick,
+                        e
+                    ));
+                    false
+                })
+            };
+            if let Some(entry) = finder::announcements::parse(&server, &message, is_bot) {
+                announcements.send(entry).ok();
+            }
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let args: Args = argh::from_env();
+
+    if args.clear_cache {
+        Cache::clear_all();
+        if args.command.is_none() && args.search.is_none() {
+            return Ok(());
+        }
+    }
+
+    let selected = match &args.command {
+        Some(Command::Latest(latest)) => pick_latest(&args, latest)?,
+        Some(Command::Subscribe(subscribe_args)) => return subscribe(subscribe_args),
+        Some(Command::Unsubscribe(unsubscribe_args)) => return unsubscribe(unsubscribe_args),
+        Some(Command::Subscriptions(_)) => return list_subscriptions(),
+        Some(Command::Sync(_)) => {
+            let _lock = Lock::acquire()?;
+            return sync(&args, None);
+        }
+        Some(Command::Daemon(daemon_args)) => return run_daemon(&args, daemon_args),
+        Some(Command::History(history_args)) => return list_history(history_args),
+        None => pick_search(&args)?,
+    };
+    let selected = match selected {
+        Some(selected) => selected,
+        None => return Ok(()),
+    };
+
+    if selected.is_empty() {
+        eprintln!("Nothing selected!");
+        return Ok(());
+    }
+
+    download(&args, &selected, None)
+}
@@ -0,0 +1,442 @@
+//! Scoring releases by how much we'd like to download them, so that
+//! `--download-first` picks what we'd have picked by hand.
+//!
+//! Preferences live in `ranking.toml` in mahou's config directory:
+//!
+//! ```toml
+//! resolutions = ["1080p", "720p"]
+//! video_codecs = ["HEVC", "AVC"]
+//! min_size = "100M"
+//! max_size = "3G"
+//!
+//! [groups]
+//! SubsPlease = 30
+//! "Some Bad Group" = -100
+//!
+//! [bots]
+//! "CR-HOLLAND|NEW" = 10
+//! ```
+
+use crate::finder::{self, Entry, EpisodeNumber, Query};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    path::PathBuf,
+};
+
+/// Points for the most preferred resolution. Each next one gets this much less.
+const RESOLUTION_POINTS: i32 = 10;
+const VIDEO_CODEC_POINTS: i32 = 5;
+const AUDIO_CODEC_POINTS: i32 = 2;
+/// Points for each revision, like v2 over v1
+const VERSION_POINTS: i32 = 5;
+/// Suspiciously small files are usually fakes or broken uploads
+const TOO_SMALL_POINTS: i32 = -50;
+const TOO_LARGE_POINTS: i32 = -20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Preferences {
+    /// Points for releases by each group. Negative to avoid a group
+    pub groups: HashMap<String, i32>,
+    /// Points for each bot, for the ones that are faster or more reliable
+    pub bots: HashMap<String, i32>,
+    /// Most preferred first
+    pub resolutions: Vec<String>,
+    /// Most preferred first, like `HEVC` or `AVC`
+    pub video_codecs: Vec<String>,
+    /// Most preferred first, like `FLAC` or `AAC`
+    pub audio_codecs: Vec<String>,
+    /// Smallest sane size of an episode, like `100M`
+    pub min_size: Option<String>,
+    /// Largest sane size of an episode, like `3G`
+    pub max_size: Option<String>,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            groups: HashMap::new(),
+            bots: HashMap::new(),
+            resolutions: vec!["1080p".into(), "720p".into(), "480p".into()],
+            video_codecs: Vec::new(),
+            audio_codecs: Vec::new(),
+            min_size: Some("50M".into()),
+            max_size: None,
+        }
+    }
+}
+
+impl Preferences {
+    /// Where the preferences are, if there's a config directory on this system
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("mahou").join("ranking.toml"))
+    }
+
+    /// Loads the preferences file, or the defaults if there's none
+    pub fn load() -> Result<Self, String> {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .parse()
+                .map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Couldn't read {}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn score(&self, entry: &Entry) -> Score {
+        let release = entry.release();
+        let mut score = Score::default();
+
+        let weight = |weights: &HashMap<String, i32>, name: &str| {
+            weights
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, &points)| points)
+        };
+        if let Some(group) = &release.group {
+            if let Some(points) = weight(&self.groups, group) {
+                score.add(points, format!("group {}", group));
+            }
+        }
+        if let Some(points) = weight(&self.bots, &entry.bot_name) {
+            score.add(points, format!("bot {}", entry.bot_name));
+        }
+
+        let ranked = |preferred: &[String], value: &Option<String>, points: i32| {
+            let value = value.as_deref()?;
+            let i = preferred
+                .iter()
+                .position(|p| p.eq_ignore_ascii_case(value))?;
+            Some((preferred.len() - i) as i32 * points)
+        };
+        if let Some(points) = ranked(&self.resolutions, &release.resolution, RESOLUTION_POINTS) {
+            score.add(points, release.resolution.clone().unwrap());
+        }
+        if let Some(points) = ranked(&self.video_codecs, &release.video_codec, VIDEO_CODEC_POINTS) {
+            score.add(points, release.video_codec.clone().unwrap());
+        }
+        if let Some(points) = ranked(&self.audio_codecs, &release.audio_codec, AUDIO_CODEC_POINTS) {
+            score.add(points, release.audio_codec.clone().unwrap());
+        }
+
+        if let Some(version) = release.version.filter(|&v| v > 1) {
+            score.add(
+                (version as i32 - 1) * VERSION_POINTS,
+                format!("v{}", version),
+            );
+        }
+
+        if let Some(size) = entry.size_bytes {
+            let episodes = match (release.episode, release.last_episode) {
+                (Some(first), Some(last)) if last > first => (last - first + 1) as u64,
+                _ => 1,
+            };
+            let per_episode = size / episodes;
+            let limit = |limit: &Option<String>| limit.as_deref().and_then(finder::parse_size);
+            if limit(&self.min_size).is_some_and(|min| per_episode < min) {
+                score.add(TOO_SMALL_POINTS, format!("too small ({})", entry.size));
+            }
+            if limit(&self.max_size).is_some_and(|max| per_episode > max) {
+                score.add(TOO_LARGE_POINTS, format!("too large ({})", entry.size));
+            }
+        }
+
+        score
+    }
+
+    /// Sorts entries from best to worst. Entries with the same score keep
+    /// their order.
+    pub fn rank(&self, entries: Vec<Entry>) -> Vec<(Entry, Score)> {
+        let mut ranked: Vec<_> = entries
+            .into_iter()
+            .map(|entry| {
+                let score = self.score(&entry);
+                (entry, score)
+            })
+            .collect();
+        ranked.sort_by_key(|(_, score)| -score.total);
+        ranked
+    }
+}
+
+/// What `--download-first` downloads out of `ranked`, which is sorted best
+/// first: the best entry of each episode if `query` selects several, or else
+/// the best entry of the highest episode found, in case the latest of some
+/// release is behind the others. Episodes are told apart by their season and
+/// episode in it, as [`Query::position`] places them. When picking several
+/// episodes, entries whose episode isn't known are skipped, since there's no
+/// telling which they are.
+pub fn pick_best(mut ranked: Vec<Entry>, query: &Query) -> Vec<Entry> {
+    let position = |entry: &Entry| {
+        entry
+            .episode
+            .map(|episode| query.position(&entry.release(), episode))
+    };
+
+    let episode = &query.episode;
+    if episode.is_many() && *episode != EpisodeNumber::All {
+        let mut seen = HashSet::new();
+        ranked.retain(|entry| position(entry).is_some_and(|p| seen.insert((p.season, p.episode))));
+        return ranked;
+    }
+
+    let highest = match episode {
+        EpisodeNumber::Latest | EpisodeNumber::BeforeLatest(_) => {
+            ranked.iter().filter_map(position).map(|p| p.absolute).max()
+        }
+        _ => None,
+    };
+    let best = ranked
+        .iter()
+        .position(|entry| highest.is_none() || position(entry).map(|p| p.absolute) == highest);
+    best.map(|i| ranked.swap_remove(i)).into_iter().collect()
+}
+
+impl std::str::FromStr for Preferences {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let preferences: Self = toml::from_str(s).map_err(|e| e.to_string())?;
+        for size in [&preferences.min_size, &preferences.max_size]
+            .into_iter()
+            .flatten()
+        {
+            if finder::parse_size(size).is_none() {
+                return Err(format!("Invalid size {}", size));
+            }
+        }
+        Ok(preferences)
+    }
+}
+
+/// How much we'd like an entry, and why
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Score {
+    pub total: i32,
+    pub reasons: Vec<(i32, String)>,
+}
+
+impl Score {
+    fn add(&mut self, points: i32, reason: String) {
+        self.total += points;
+        self.reasons.push((points, reason));
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.total)?;
+        for (i, (points, reason)) in self.reasons.iter().enumerate() {
+            let separator = if i == 0 { " (" } else { ", " };
+            write!(f, "{}{:+} {}", separator, points, reason)?;
+        }
+        if !self.reasons.is_empty() {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preferences(toml: &str) -> Preferences {
+        toml.parse().unwrap()
+    }
+
+    fn score(preferences: &Preferences, name: &str, bot: &str, size: &str) -> i32 {
+        preferences.score(&Entry::for_tests(name, bot, size)).total
+    }
+
+    #[test]
+    fn weights() {
+        let preferences = preferences(
+            r#"
+            resolutions = []
+            [groups]
+            SubsPlease = 30
+            "Bad Group" = -100
+            [bots]
+            "CR-HOLLAND|NEW" = 10
+            "#,
+        );
+        let score = |name, bot| score(&preferences, name, bot, "1G");
+        assert_eq!(score("[SubsPlease] Show - 01.mkv", "Bot"), 30);
+        assert_eq!(score("[subsplease] Show - 01.mkv", "cr-holland|new"), 40);
+        assert_eq!(score("[Bad Group] Show - 01.mkv", "CR-HOLLAND|NEW"), -90);
+        assert_eq!(score("[Other] Show - 01.mkv", "Bot"), 0);
+    }
+
+    #[test]
+    fn resolutions_and_versions() {
+        let preferences = Preferences::default();
+        let score = |name| score(&preferences, name, "Bot", "1G");
+        assert_eq!(score("[G] Show - 01 (1080p).mkv"), 30);
+        assert_eq!(score("[G] Show - 01 (720p).mkv"), 20);
+        assert_eq!(score("[G] Show - 01 (480p).mkv"), 10);
+        assert_eq!(score("[G] Show - 01 (2160p).mkv"), 0);
+        assert_eq!(score("[G] Show - 01v2 (1080p).mkv"), 35);
+        assert_eq!(score("[G] Show - 01v3 (720p).mkv"), 30);
+
+        let score = Preferences::default().score(&Entry::for_tests(
+            "[G] Show - 01v2 (1080p).mkv",
+            "Bot",
+            "1G",
+        ));
+        assert_eq!(score.to_string(), "35 (+30 1080p, +5 v2)");
+    }
+
+    #[test]
+    fn sizes_per_episode() {
+        let preferences = preferences(
+            r#"
+            resolutions = []
+            min_size = "100M"
+            max_size = "2G"
+            "#,
+        );
+        let score = |name, size| score(&preferences, name, "Bot", size);
+        assert_eq!(score("[G] Show - 01.mkv", "1G"), 0);
+        assert_eq!(score("[G] Show - 01.mkv", "20M"), TOO_SMALL_POINTS);
+        assert_eq!(score("[G] Show - 01.mkv", "3G"), TOO_LARGE_POINTS);
+        // 12 episodes of 1G each isn't too large, nor 12 of 50M too small
+        // for a single episode
+        assert_eq!(score("[G] Show (01-12) [Batch]", "12G"), 0);
+        assert_eq!(score("[G] Show (01-12) [Batch]", "600M"), TOO_SMALL_POINTS);
+        // Unknown sizes aren't judged
+        assert_eq!(score("[G] Show - 01.mkv", "?"), 0);
+    }
+
+    #[test]
+    fn ties_keep_their_order() {
+        let entries: Vec<_> = [
+            "[A] Show - 01 (720p).mkv",
+            "[B] Show - 01 (1080p).mkv",
+            "[C] Show - 01 (720p).mkv",
+            "[D] Show - 01 (1080p).mkv",
+        ]
+        .iter()
+        .map(|name| Entry::for_tests(name, "Bot", "1G"))
+        .collect();
+        let ranked: Vec<_> = Preferences::default()
+            .rank(entries)
+            .into_iter()
+            .map(|(entry, _)| entry.release().group.unwrap())
+            .collect();
+        assert_eq!(ranked, ["B", "D", "A", "C"]);
+    }
+
+    #[test]
+    fn best_of_the_highest_episode() {
+        let preferences = preferences(
+            r#"
+            [groups]
+            G = 30
+            "#,
+        );
+        let entries: Vec<_> = [
+            "[G] Show - 05 (1080p).mkv",
+            "[Other] Show - 07 (720p).mkv",
+            "[Other] Show - 07 (1080p).mkv",
+        ]
+        .iter()
+        .map(|name| Entry::for_tests(name, "Bot", "1G"))
+        .collect();
+        let names = |episode| -> Vec<_> {
+            let ranked = preferences.rank(entries.clone());
+            let ranked = ranked.into_iter().map(|(entry, _)| entry).collect();
+            pick_best(ranked, &Query::new("Show".into(), None, episode))
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect()
+        };
+        assert_eq!(
+            names(EpisodeNumber::Latest),
+            ["[Other] Show - 07 (1080p).mkv"]
+        );
+        assert_eq!(names(EpisodeNumber::All), ["[G] Show - 05 (1080p).mkv"]);
+        let query = Query::new("Show".into(), None, EpisodeNumber::Latest);
+        assert!(pick_best(Vec::new(), &query).is_empty());
+    }
+
+    #[test]
+    fn best_of_each_episode() {
+        let entries: Vec<_> = [
+            "[A] Show - 01 (720p).mkv",
+            "[B] Show - 01 (1080p).mkv",
+            "[A] Show - 02 (720p).mkv",
+            "[A] Show Special (1080p).mkv",
+            "[B] Show OVA (1080p).mkv",
+        ]
+        .iter()
+        .map(|name| Entry::for_tests(name, "Bot", "1G"))
+        .collect();
+        let ranked = Preferences::default()
+            .rank(entries)
+            .into_iter()
+            .map(|(entry, _)| entry)
+            .collect();
+        let query = Query::new("Show".into(), None, "1-2".parse().unwrap());
+        let names: Vec<_> = pick_best(ranked, &query)
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(
+            names,
+            ["[B] Show - 01 (1080p).mkv", "[A] Show - 02 (720p).mkv"]
+        );
+    }
+
+    #[test]
+    fn best_of_each_episode_of_each_season() {
+        let entries: Vec<_> = [
+            "[A] Show S01E01 (1080p).mkv",
+            "[A] Show S02E01 (1080p).mkv",
+            "[B] Show - 13 (720p).mkv",
+            "[B] Show - 01 (720p).mkv",
+            "[B] Show - 14 (720p).mkv",
+        ]
+        .iter()
+        .map(|name| Entry::for_tests(name, "Bot", "1G"))
+        .collect();
+        let ranked = Preferences::default()
+            .rank(entries)
+            .into_iter()
+            .map(|(entry, _)| entry)
+            .collect();
+        let seasons = "Show = { 1 = 1, 2 = 13 }".parse().unwrap();
+        let query = Query::new("Show".into(), None, "1-14".parse().unwrap()).with_seasons(seasons);
+        let names: Vec<_> = pick_best(ranked, &query)
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        // Episode 13 is S02E01
+        assert_eq!(
+            names,
+            [
+                "[A] Show S01E01 (1080p).mkv",
+                "[A] Show S02E01 (1080p).mkv",
+                "[B] Show - 14 (720p).mkv"
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_preferences() {
+        assert!("min_size = \"huge\"".parse::<Preferences>().is_err());
+        assert!("max_size = \"3 gigs\"".parse::<Preferences>().is_err());
+        assert!("colour = \"blue\"".parse::<Preferences>().is_err());
+        assert_eq!("".parse::<Preferences>(), Ok(Preferences::default()));
+        assert_eq!(
+            "max_size = \"3G\"".parse::<Preferences>().unwrap().max_size,
+            Some("3G".to_owned())
+        );
+    }
+}
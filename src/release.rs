@@ -0,0 +1,533 @@
+//! Parsing for fansub and scene release names, like
+//! `[SubsPlease] Show Name - 05v2 (1080p) [ABCD1234].mkv`.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref GROUP_REGEX: Regex = Regex::new(r"^\s*\[([^\]]+)\]").unwrap();
+    static ref TRAILING_GROUP_REGEX: Regex = Regex::new(r"-([A-Za-z0-9]+)$").unwrap();
+    static ref EXTENSION_REGEX: Regex =
+        Regex::new(r"(?i)\.(mkv|mp4|avi|m4v|webm|ts|ogm|wmv|flv|mov|srt|ass|zip|rar|7z)$")
+            .unwrap();
+    static ref TAG_REGEX: Regex = Regex::new(r"\[[^\]]*\]|\([^)]*\)|\{[^}]*\}").unwrap();
+    static ref SEASON_EPISODE_REGEX: Regex =
+        Regex::new(r"(?i)\bS(\d{1,2})\s*E(\d{1,4})(?:v(\d))?(?:\s*-\s*E?(\d{1,4}))?\b").unwrap();
+    static ref SEASON_REGEX: Regex =
+        Regex::new(r"(?i)\b(?:S(\d{1,2})|Season\s*(\d{1,2})|(\d{1,2})(?:st|nd|rd|th)\s+Season)\b")
+            .unwrap();
+    static ref BATCH_REGEX: Regex =
+        Regex::new(r"(?:^|\s-\s|\s|\(|\[)(\d{1,4})(?:-|\s*~\s*)(\d{1,4})(?:\s|\)|\]|$)").unwrap();
+    static ref EPISODE_REGEX: Regex =
+        Regex::new(r"(?i)(?:\s-\s|\bE|\bEp\.?\s*|\bEpisode\s+|#)(\d{1,4})(?:v(\d))?\b").unwrap();
+    static ref BARE_EPISODE_REGEX: Regex = Regex::new(r"\s(0?\d{2})(?:v(\d))?\s*$").unwrap();
+    static ref VERSION_REGEX: Regex = Regex::new(r"(?i)\bv(\d)\b").unwrap();
+    static ref RESOLUTION_REGEX: Regex =
+        Regex::new(r"(?i)(?:(\d{3,4})[pi]|\b\d{3,4}x(\d{3,4})|\b(4K|UHD))\b").unwrap();
+    static ref CRC_REGEX: Regex = Regex::new(r"[\[(]([0-9A-Fa-f]{8})[\])]").unwrap();
+    static ref SOURCE_REGEX: Regex = Regex::new(
+        r"(?i)\b(BD(?:Rip|Remux)?|Blu-?Ray|WEB(?:-?DL|-?Rip)?|DVD(?:Rip)?|HDTV(?:Rip)?|TV(?:Rip)?|VHS(?:Rip)?|LD(?:Rip)?)(?:\b|\d)"
+    )
+    .unwrap();
+    static ref VIDEO_CODEC_REGEX: Regex =
+        Regex::new(r"(?i)\b(HEVC|[xh]\.?265|AVC|[xh]\.?264|AV1|VP9|XviD|DivX|MPEG-?2)\b").unwrap();
+    static ref AUDIO_CODEC_REGEX: Regex = Regex::new(
+        r"(?i)\b(?:(DD\+)|(AAC(?:\s?[257]\.[01])?|FLAC|Opus|E-?AC-?3|DDP?(?:\s?[257]\.[01])?|AC-?3|DTS(?:-HD(?:\s?MA)?)?|TrueHD|MP3|Vorbis|LPCM|PCM)\b)"
+    )
+    .unwrap();
+    static ref AUDIO_CHANNELS_REGEX: Regex = Regex::new(r"[257]\.[01]$").unwrap();
+}
+
+/// Information extracted from a release's file name
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct ReleaseInfo {
+    pub group: Option<String>,
+    pub title: Option<String>,
+    pub season: Option<u32>,
+    /// The episode, or the first episode of a batch
+    pub episode: Option<i32>,
+    /// The last episode of a batch, like `01-12`
+    pub last_episode: Option<i32>,
+    /// Always in the `1080p` form
+    pub resolution: Option<String>,
+    /// Normalized to `BD`, `WEB`, `DVD`, `TV`, `VHS` or `LD`
+    pub source: Option<String>,
+    /// Normalized to `HEVC`, `AVC`, `AV1`, ...
+    pub video_codec: Option<String>,
+    /// Normalized to `AAC`, `FLAC`, `OPUS`, `EAC3`, `AC3`, ...
+    pub audio_codec: Option<String>,
+    /// Uppercase, like `ABCD1234`
+    pub crc: Option<String>,
+    /// Like the 2 in `05v2`. Releases without a version are version 1
+    pub version: Option<u32>,
+    pub extension: Option<String>,
+}
+
+impl ReleaseInfo {
+    pub fn parse(name: &str) -> Self {
+        let mut info = Self::default();
+        let mut rest = name.trim();
+
+        if let Some(caps) = EXTENSION_REGEX.captures(rest) {
+            info.extension = Some(caps[1].to_lowercase());
+            rest = &rest[..caps.get(0).unwrap().start()];
+        }
+
+        if let Some(caps) = GROUP_REGEX.captures(rest) {
+            info.group = Some(caps[1].trim().to_owned());
+            rest = &rest[caps.get(0).unwrap().end()..];
+        }
+
+        // Technical details can be anywhere, tagged or not
+        info.parse_details(rest);
+
+        // Tags like (1080p) or [ABCD1234] never belong to the title
+        let mut rest = TAG_REGEX.replace_all(rest, " ").replace('_', " ");
+        if !rest.trim().contains(' ') {
+            // Scene-style Show.Name.S01E02.1080p.WEB.x264-GROUP names
+            if info.group.is_none() {
+                if let Some(caps) = TRAILING_GROUP_REGEX.captures(rest.trim()) {
+                    info.group = Some(caps[1].to_owned());
+                }
+            }
+            rest = rest.replace('.', " ");
+        }
+
+        let title_end = info.parse_episode(name, &rest);
+        if info.season.is_none() {
+            // Maybe it's tagged, like (Season 2)
+            info.season = SEASON_REGEX
+                .captures_iter(name)
+                .flat_map(|caps| caps.iter().skip(1).flatten().collect::<Vec<_>>())
+                .find_map(|m| m.as_str().parse().ok());
+        }
+        let title = rest[..title_end]
+            .trim()
+            .trim_end_matches(|c: char| c == '-' || c.is_whitespace());
+        if !title.is_empty() {
+            info.title = Some(title.split_whitespace().collect::<Vec<_>>().join(" "));
+        }
+
+        info
+    }
+
+    /// Whether this release contains several episodes
+    pub fn is_batch(&self) -> bool {
+        self.last_episode.is_some()
+    }
+
+    /// Parses the season and episode numbers, and returns where the title ends
+    fn parse_episode(&mut self, name: &str, rest: &str) -> usize {
+        let mut title_end = rest.len();
+
+        if let Some(caps) = SEASON_EPISODE_REGEX.captures(rest) {
+            self.season = caps[1].parse().ok();
+            self.episode = caps[2].parse().ok();
+            self.version = self.version.or_else(|| caps.get(3)?.as_str().parse().ok());
+            self.last_episode = caps.get(4).and_then(|m| m.as_str().parse().ok());
+            return caps.get(0).unwrap().start();
+        }
+
+        // Batches are often tagged, like (01-12), so they're also looked for
+        // in the whole name. Years like (2019-2020) are skipped.
+        let find_batch = |s: &str| {
+            BATCH_REGEX.captures_iter(s).find_map(|caps| {
+                let first: i32 = caps[1].parse().ok()?;
+                let last: i32 = caps[2].parse().ok()?;
+                (first < last && last < 1900).then_some((first, last, caps.get(0).unwrap().start()))
+            })
+        };
+
+        if let Some((first, last, start)) = find_batch(rest) {
+            self.episode = Some(first);
+            self.last_episode = Some(last);
+            title_end = start;
+        } else if let Some((first, last, _)) = find_batch(name) {
+            self.episode = Some(first);
+            self.last_episode = Some(last);
+        } else if let Some(caps) = EPISODE_REGEX
+            .captures(rest)
+            .or_else(|| BARE_EPISODE_REGEX.captures(rest))
+        {
+            self.episode = caps[1].parse().ok();
+            self.version = self.version.or_else(|| caps.get(2)?.as_str().parse().ok());
+            title_end = caps.get(0).unwrap().start();
+        }
+
+        if let Some(caps) = SEASON_REGEX.captures(&rest[..title_end]) {
+            self.season = caps
+                .iter()
+                .skip(1)
+                .flatten()
+                .find_map(|m| m.as_str().parse().ok());
+            title_end = caps.get(0).unwrap().start();
+        }
+
+        title_end
+    }
+
+    fn parse_details(&mut self, rest: &str) {
+        self.resolution =
+            RESOLUTION_REGEX
+                .captures(rest)
+                .map(|caps| match (caps.get(1), caps.get(2)) {
+                    (Some(height), _) | (_, Some(height)) => format!("{}p", height.as_str()),
+                    _ => "2160p".to_owned(),
+                });
+
+        self.crc = CRC_REGEX
+            .captures_iter(rest)
+            .last()
+            .map(|caps| caps[1].to_uppercase());
+
+        self.version = VERSION_REGEX
+            .captures(rest)
+            .and_then(|caps| caps[1].parse().ok());
+
+        self.source = SOURCE_REGEX.captures(rest).map(|caps| {
+            let source = caps[1].to_uppercase();
+            match source.as_str() {
+                s if s.starts_with("BD") || s.starts_with("BLU") => "BD",
+                s if s.contains("WEB") => "WEB",
+                s if s.starts_with("DVD") => "DVD",
+                s if s.starts_with("HDTV") || s.starts_with("TV") => "TV",
+                s if s.starts_with("VHS") => "VHS",
+                _ => "LD",
+            }
+            .to_owned()
+        });
+
+        self.video_codec = VIDEO_CODEC_REGEX.captures(rest).map(|caps| {
+            let codec = caps[1].to_uppercase().replace(['.', '-'], "");
+            match codec.as_str() {
+                "HEVC" | "X265" | "H265" => "HEVC".to_owned(),
+                "AVC" | "X264" | "H264" => "AVC".to_owned(),
+                _ => codec,
+            }
+        });
+
+        self.audio_codec = AUDIO_CODEC_REGEX.captures(rest).map(|caps| {
+            let codec = caps.get(1).or(caps.get(2)).unwrap().as_str();
+            let codec = codec
+                .to_uppercase()
+                .replace(['-', ' '], "")
+                .replace('+', "P");
+            match AUDIO_CHANNELS_REGEX.replace(&codec, "").as_ref() {
+                "DDP" | "EAC3" => "EAC3".to_owned(),
+                "DD" | "AC3" => "AC3".to_owned(),
+                "DTSHD" | "DTSHDMA" => "DTS-HD".to_owned(),
+                codec => codec.to_owned(),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(s: &str) -> Option<String> {
+        Some(s.to_owned())
+    }
+
+    #[test]
+    fn real_world_names() {
+        let cases = [
+            (
+                "[SubsPlease] Sousou no Frieren - 05 (1080p) [ABCD1234].mkv",
+                ReleaseInfo {
+                    group: s("SubsPlease"),
+                    title: s("Sousou no Frieren"),
+                    episode: Some(5),
+                    resolution: s("1080p"),
+                    crc: s("ABCD1234"),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[SubsPlease] Spy x Family - 12v2 (720p) [0F1E2D3C].mkv",
+                ReleaseInfo {
+                    group: s("SubsPlease"),
+                    title: s("Spy x Family"),
+                    episode: Some(12),
+                    resolution: s("720p"),
+                    crc: s("0F1E2D3C"),
+                    version: Some(2),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[Erai-raws] Shingeki no Kyojin - The Final Season - 28 [1080p][Multiple Subtitle][5A9B3C1D].mkv",
+                ReleaseInfo {
+                    group: s("Erai-raws"),
+                    title: s("Shingeki no Kyojin - The Final Season"),
+                    episode: Some(28),
+                    resolution: s("1080p"),
+                    crc: s("5A9B3C1D"),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[Judas] Vinland Saga S2 - 03 [1080p][HEVC x265 10bit][Eng-Subs].mkv",
+                ReleaseInfo {
+                    group: s("Judas"),
+                    title: s("Vinland Saga"),
+                    season: Some(2),
+                    episode: Some(3),
+                    resolution: s("1080p"),
+                    video_codec: s("HEVC"),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[ASW] Mushoku Tensei S2 - 01 [1080p HEVC x265 10Bit][AAC]",
+                ReleaseInfo {
+                    group: s("ASW"),
+                    title: s("Mushoku Tensei"),
+                    season: Some(2),
+                    episode: Some(1),
+                    resolution: s("1080p"),
+                    video_codec: s("HEVC"),
+                    audio_codec: s("AAC"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[Anime Time] Jujutsu Kaisen (Season 2) - 05 [1080p][HEVC 10bit x265][AAC][Multi Sub].mkv",
+                ReleaseInfo {
+                    group: s("Anime Time"),
+                    title: s("Jujutsu Kaisen"),
+                    season: Some(2),
+                    episode: Some(5),
+                    resolution: s("1080p"),
+                    video_codec: s("HEVC"),
+                    audio_codec: s("AAC"),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[Cleo] Kimetsu no Yaiba 2nd Season - 07 (Dual Audio 10bit BD1080p x265).mkv",
+                ReleaseInfo {
+                    group: s("Cleo"),
+                    title: s("Kimetsu no Yaiba"),
+                    season: Some(2),
+                    episode: Some(7),
+                    resolution: s("1080p"),
+                    source: s("BD"),
+                    video_codec: s("HEVC"),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[Beatrice-Raws] Tengen Toppa Gurren Lagann [BDRip 1920x1080 HEVC FLAC]",
+                ReleaseInfo {
+                    group: s("Beatrice-Raws"),
+                    title: s("Tengen Toppa Gurren Lagann"),
+                    resolution: s("1080p"),
+                    source: s("BD"),
+                    video_codec: s("HEVC"),
+                    audio_codec: s("FLAC"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[Coalgirls] Clannad After Story (1920x1080 Blu-Ray FLAC) [1CE8B0D9]",
+                ReleaseInfo {
+                    group: s("Coalgirls"),
+                    title: s("Clannad After Story"),
+                    resolution: s("1080p"),
+                    source: s("BD"),
+                    audio_codec: s("FLAC"),
+                    crc: s("1CE8B0D9"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[SubsPlease] Bocchi the Rock! (01-12) (1080p) [Batch]",
+                ReleaseInfo {
+                    group: s("SubsPlease"),
+                    title: s("Bocchi the Rock!"),
+                    episode: Some(1),
+                    last_episode: Some(12),
+                    resolution: s("1080p"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[DB] Cowboy Bebop [Dual Audio 10bit BD1080p][HEVC-x265] 01-26",
+                ReleaseInfo {
+                    group: s("DB"),
+                    title: s("Cowboy Bebop"),
+                    episode: Some(1),
+                    last_episode: Some(26),
+                    resolution: s("1080p"),
+                    source: s("BD"),
+                    video_codec: s("HEVC"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[HorribleSubs] One Punch Man - 01 [480p].mkv",
+                ReleaseInfo {
+                    group: s("HorribleSubs"),
+                    title: s("One Punch Man"),
+                    episode: Some(1),
+                    resolution: s("480p"),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[Golumpa] Dr. Stone - 10 (Dr. Stone) [FuniDub 1080p x264 AAC] [MKV] [8B2A7E1F].mkv",
+                ReleaseInfo {
+                    group: s("Golumpa"),
+                    title: s("Dr. Stone"),
+                    episode: Some(10),
+                    resolution: s("1080p"),
+                    video_codec: s("AVC"),
+                    audio_codec: s("AAC"),
+                    crc: s("8B2A7E1F"),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "Frieren.Beyond.Journeys.End.S01E05.1080p.WEB.H264-SENPAI.mkv",
+                ReleaseInfo {
+                    group: s("SENPAI"),
+                    title: s("Frieren Beyond Journeys End"),
+                    season: Some(1),
+                    episode: Some(5),
+                    resolution: s("1080p"),
+                    source: s("WEB"),
+                    video_codec: s("AVC"),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "Chainsaw.Man.S01E01.Dog.and.Chainsaw.1080p.CR.WEB-DL.AAC2.0.H.264-VARYG.mkv",
+                ReleaseInfo {
+                    group: s("VARYG"),
+                    title: s("Chainsaw Man"),
+                    season: Some(1),
+                    episode: Some(1),
+                    resolution: s("1080p"),
+                    source: s("WEB"),
+                    video_codec: s("AVC"),
+                    audio_codec: s("AAC"),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[EMBER] Oshi no Ko S01E11 [1080p] [HEVC WEBRip DDP].mkv",
+                ReleaseInfo {
+                    group: s("EMBER"),
+                    title: s("Oshi no Ko"),
+                    season: Some(1),
+                    episode: Some(11),
+                    resolution: s("1080p"),
+                    source: s("WEB"),
+                    video_codec: s("HEVC"),
+                    audio_codec: s("EAC3"),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[Yameii] Dungeon Meshi - S01E03 [English Dub] [CR WEB-DL 1080p] [F0E1D2C3].mkv",
+                ReleaseInfo {
+                    group: s("Yameii"),
+                    title: s("Dungeon Meshi"),
+                    season: Some(1),
+                    episode: Some(3),
+                    resolution: s("1080p"),
+                    source: s("WEB"),
+                    crc: s("F0E1D2C3"),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "Made in Abyss S02E01-12 [1080p AV1 Opus]",
+                ReleaseInfo {
+                    title: s("Made in Abyss"),
+                    season: Some(2),
+                    episode: Some(1),
+                    last_episode: Some(12),
+                    resolution: s("1080p"),
+                    video_codec: s("AV1"),
+                    audio_codec: s("OPUS"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[Nep_Blanc] Toradora! 05 .mkv",
+                ReleaseInfo {
+                    group: s("Nep_Blanc"),
+                    title: s("Toradora!"),
+                    episode: Some(5),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[neoHEVC] Haikyuu!! - Season 4 [BD 1080p x265 HEVC AAC]",
+                ReleaseInfo {
+                    group: s("neoHEVC"),
+                    title: s("Haikyuu!!"),
+                    season: Some(4),
+                    resolution: s("1080p"),
+                    source: s("BD"),
+                    video_codec: s("HEVC"),
+                    audio_codec: s("AAC"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[SubsPlease] Kusuriya no Hitorigoto - 13 (4K) [ABCDEF12].mkv",
+                ReleaseInfo {
+                    group: s("SubsPlease"),
+                    title: s("Kusuriya no Hitorigoto"),
+                    episode: Some(13),
+                    resolution: s("2160p"),
+                    crc: s("ABCDEF12"),
+                    extension: s("mkv"),
+                    ..Default::default()
+                },
+            ),
+            (
+                "[Kametsu] Nichijou - Episode 26 [BD 720p Hi10P FLAC]",
+                ReleaseInfo {
+                    group: s("Kametsu"),
+                    title: s("Nichijou"),
+                    episode: Some(26),
+                    resolution: s("720p"),
+                    source: s("BD"),
+                    audio_codec: s("FLAC"),
+                    ..Default::default()
+                },
+            ),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(ReleaseInfo::parse(name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn batches() {
+        assert!(
+            ReleaseInfo::parse("[SubsPlease] Bocchi the Rock! (01-12) (1080p) [Batch]").is_batch()
+        );
+        assert!(!ReleaseInfo::parse("[SubsPlease] Bocchi the Rock! - 12 (1080p).mkv").is_batch());
+        // Years aren't episode ranges
+        assert!(!ReleaseInfo::parse("[Group] Show (2019-2020) - 05 [1080p].mkv").is_batch());
+    }
+}
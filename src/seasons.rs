@@ -0,0 +1,139 @@
+//! Mapping between season-relative episode numbers, like `S02E03`, and the
+//! absolute numbering some shows and release groups use instead.
+//!
+//! Mappings live in `seasons.toml` in mahou's config directory, and list the
+//! first absolute episode of each season:
+//!
+//! ```toml
+//! "Shingeki no Kyojin" = { 1 = 1, 2 = 26, 3 = 38 }
+//! ```
+
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::PathBuf,
+};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct SeasonMap {
+    /// Normalized show title -> (season, first absolute episode), sorted by
+    /// season
+    shows: BTreeMap<String, Vec<(u32, i32)>>,
+}
+
+impl SeasonMap {
+    /// Where the mapping file is, if there's a config directory on this system
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("mahou").join("seasons.toml"))
+    }
+
+    /// Loads the mapping file. A missing file is just an empty mapping.
+    pub fn load() -> Result<Self, String> {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .parse()
+                .map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Couldn't read {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Whether there's a mapping for `show`
+    pub fn contains(&self, show: &str) -> bool {
+        self.shows.contains_key(&normalize(show))
+    }
+
+    /// The same mapping, where every one of `names` shares the mapping of the
+    /// first one that has any, so that it's found whichever name the show is
+    /// searched by
+    pub fn with_aliases(&self, names: &[String]) -> Self {
+        let mut map = self.clone();
+        if let Some(seasons) = names
+            .iter()
+            .find_map(|name| self.shows.get(&normalize(name)))
+        {
+            for name in names {
+                map.shows
+                    .entry(normalize(name))
+                    .or_insert_with(|| seasons.clone());
+            }
+        }
+        map
+    }
+
+    /// The absolute number of an episode of `show`
+    pub fn to_absolute(&self, show: &str, season: u32, episode: i32) -> Option<i32> {
+        let seasons = self.shows.get(&normalize(show))?;
+        let &(_, first) = seasons.iter().find(|(s, _)| *s == season)?;
+        Some(first + episode - 1)
+    }
+
+    /// The season and season-relative number of an absolutely numbered
+    /// episode of `show`
+    pub fn to_relative(&self, show: &str, absolute: i32) -> Option<(u32, i32)> {
+        let seasons = self.shows.get(&normalize(show))?;
+        let &(season, first) = seasons.iter().rev().find(|(_, first)| *first <= absolute)?;
+        Some((season, absolute - first + 1))
+    }
+}
+
+impl std::str::FromStr for SeasonMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        #[derive(Deserialize)]
+        #[serde(transparent)]
+        struct File(HashMap<String, BTreeMap<String, i32>>);
+
+        let File(file) = toml::from_str(s).map_err(|e| e.to_string())?;
+        let mut shows = BTreeMap::new();
+        for (show, seasons) in file {
+            let mut seasons = seasons
+                .into_iter()
+                .map(|(season, first)| match season.parse::<u32>() {
+                    Ok(season) if first > 0 => Ok((season, first)),
+                    _ => Err(format!(
+                        "Invalid season {} = {} for {}",
+                        season, first, show
+                    )),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            seasons.sort_unstable();
+            shows.insert(normalize(&show), seasons);
+        }
+        Ok(Self { shows })
+    }
+}
+
+/// Titles are matched case-insensitively, ignoring extra whitespace
+fn normalize(show: &str) -> String {
+    show.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_share_mappings() {
+        let map: SeasonMap = r#""Shingeki no Kyojin" = { 1 = 1, 2 = 26 }"#.parse().unwrap();
+        assert_eq!(map.to_absolute("Attack on Titan", 2, 3), None);
+
+        let names = [
+            "Attack on Titan".to_owned(),
+            "Shingeki no Kyojin".to_owned(),
+        ];
+        let map = map.with_aliases(&names);
+        assert_eq!(map.to_absolute("attack on  titan", 2, 3), Some(28));
+        assert_eq!(map.to_relative("Attack on Titan", 28), Some((2, 3)));
+        assert_eq!(map.to_absolute("Shingeki no Kyojin", 2, 3), Some(28));
+    }
+}
@@ -0,0 +1,276 @@
+//! Shows we're following, and which of their episodes were already
+//! downloaded, so that `mahou sync` only downloads new ones.
+
+use crate::{
+    filter::Filter,
+    finder::{Entry, EpisodeNumber, Query},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt, fs, io,
+    path::PathBuf,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subscription {
+    pub show: String,
+    pub resolution: Option<String>,
+    /// Only download releases by this group
+    pub group: Option<String>,
+    /// Only download releases matching this filter, see [`crate::filter`]
+    pub filter: Option<String>,
+    /// Episodes before this one are ignored
+    pub from: Option<i32>,
+    #[serde(default)]
+    pub downloaded: BTreeSet<i32>,
+}
+
+impl Subscription {
+    pub fn new(show: String) -> Self {
+        Self {
+            show,
+            resolution: None,
+            group: None,
+            filter: None,
+            from: None,
+            downloaded: BTreeSet::new(),
+        }
+    }
+
+    /// What to search for to find new episodes
+    pub fn query(&self) -> Query {
+        let episode = match self.from {
+            Some(from) => EpisodeNumber::From(from),
+            None => EpisodeNumber::All,
+        };
+        Query::new(self.show.clone(), self.resolution.clone(), episode)
+            .with_group(self.group.as_deref())
+    }
+
+    /// The entries for episodes that weren't downloaded yet, one per episode.
+    /// `entries` should be sorted from best to worst, as the first entry of
+    /// each episode is picked. Only releases of the show itself count, by any
+    /// of `names`, like from [`crate::finder::Aliases::expand`], since the
+    /// search also finds other shows with the same words in their names.
+    /// Fails if the subscription's filter is invalid, rather than downloading
+    /// everything.
+    pub fn pending(&self, entries: Vec<Entry>, names: &[String]) -> Result<Vec<Entry>, String> {
+        let filter = self
+            .filter
+            .as_deref()
+            .map(str::parse::<Filter>)
+            .transpose()
+            .map_err(|e| format!("Invalid filter for {}: {}", self.show, e))?;
+        let queries: Vec<_> = names
+            .iter()
+            .map(|name| Query::new(name.clone(), None, EpisodeNumber::All))
+            .collect();
+        let mut seen = HashSet::new();
+        let pending = entries
+            .into_iter()
+            .filter(|entry| {
+                let release = entry.release();
+                // Batches would download everything all over again
+                if release.is_batch() || !queries.iter().any(|q| q.is_show(&release)) {
+                    return false;
+                }
+                let group_matches = match &self.group {
+                    Some(group) => release.group.is_some_and(|g| g.eq_ignore_ascii_case(group)),
+                    None => true,
+                };
+                let filter_matches = match &filter {
+                    Some(filter) => filter.matches(entry),
+                    None => true,
+                };
+                group_matches && filter_matches
+            })
+            .filter(|entry| match entry.episode {
+                Some(episode) => !self.downloaded.contains(&episode) && seen.insert(episode),
+                None => false,
+            })
+            .collect();
+        Ok(pending)
+    }
+
+    /// Whether `entry`, which wasn't found by searching for the show, is an
+    /// episode we still want. `names` are every name of the show, as in
+    /// [`Subscription::pending`]. Nothing is wanted while the filter is
+    /// invalid.
+    pub fn wants(&self, entry: &Entry, names: &[String]) -> bool {
+        let query = self.query();
+        let named = names.iter().any(|name| {
+            let query = Query {
+                search: name.clone(),
+                ..query.clone()
+            };
+            query.matches_name(&entry.name)
+        });
+        let too_old =
+            matches!((entry.episode, self.from), (Some(episode), Some(from)) if episode < from);
+        named
+            && !too_old
+            && self
+                .pending(vec![entry.clone()], names)
+                .is_ok_and(|pending| !pending.is_empty())
+    }
+
+    pub fn mark_downloaded(&mut self, episodes: impl IntoIterator<Item = i32>) {
+        self.downloaded.extend(episodes);
+    }
+}
+
+impl fmt::Display for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.show)?;
+        for (name, value) in [
+            ("res", &self.resolution),
+            ("group", &self.group),
+            ("filter", &self.filter),
+        ] {
+            if let Some(value) = value {
+                write!(f, " {}:{}", name, value)?;
+            }
+        }
+        if let Some(from) = self.from {
+            write!(f, " from:{}", from)?;
+        }
+        match (self.downloaded.first(), self.downloaded.last()) {
+            (Some(first), Some(last)) if first != last => write!(
+                f,
+                " ({} episodes downloaded, {}-{})",
+                self.downloaded.len(),
+                first,
+                last
+            ),
+            (Some(only), _) => write!(f, " (episode {} downloaded)", only),
+            _ => write!(f, " (nothing downloaded yet)"),
+        }
+    }
+}
+
+/// Every subscription, as saved on disk
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subscriptions {
+    pub subscriptions: Vec<Subscription>,
+}
+
+impl Subscriptions {
+    /// Where subscriptions are saved, if there's a data directory on this
+    /// system
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::data_dir()?.join("mahou").join("subscriptions.json"))
+    }
+
+    pub fn load() -> Result<Self, String> {
+        let path = Self::path().ok_or("There's no data directory to keep subscriptions in")?;
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Couldn't read {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Writes to a temporary file first, so that subscriptions aren't lost if
+    /// mahou is stopped halfway through
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("There's no data directory to keep subscriptions in")?;
+        let write = || {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let temp = path.with_extension("json.tmp");
+            fs::write(&temp, serde_json::to_string_pretty(self)?)?;
+            fs::rename(&temp, &path)
+        };
+        write().map_err(|e: io::Error| format!("Couldn't save {}: {}", path.display(), e))
+    }
+
+    pub fn get_mut(&mut self, show: &str) -> Option<&mut Subscription> {
+        self.subscriptions
+            .iter_mut()
+            .find(|s| s.show.eq_ignore_ascii_case(show))
+    }
+
+    /// Adds a subscription, replacing the one for the same show but keeping
+    /// its progress
+    pub fn add(&mut self, mut subscription: Subscription) {
+        match self.get_mut(&subscription.show) {
+            Some(existing) => {
+                subscription.downloaded = std::mem::take(&mut existing.downloaded);
+                *existing = subscription;
+            }
+            None => self.subscriptions.push(subscription),
+        }
+    }
+
+    /// Returns whether there was a subscription for `show`
+    pub fn remove(&mut self, show: &str) -> bool {
+        let before = self.subscriptions.len();
+        self.subscriptions
+            .retain(|s| !s.show.eq_ignore_ascii_case(show));
+        self.subscriptions.len() != before
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str) -> Entry {
+        Entry::for_tests(name, "Bot", "1G")
+    }
+
+    #[test]
+    fn pending_episodes() {
+        let mut subscription = Subscription::new("Frieren".to_owned());
+        subscription.group = Some("SubsPlease".to_owned());
+        subscription.mark_downloaded([4]);
+        let show = ["Frieren".to_owned(), "Sousou no Frieren".to_owned()];
+        let entries = vec![
+            entry("[SubsPlease] Frieren Mini Anime - 07 (1080p).mkv"),
+            entry("[SubsPlease] Frieren - 05 (1080p).mkv"),
+            entry("[SubsPlease] Frieren - 05 (720p).mkv"),
+            entry("[SubsPlease] Frieren - 04 (1080p).mkv"),
+            entry("[Erai-raws] Frieren - 06 [1080p].mkv"),
+            entry("[SubsPlease] Frieren (01-12) (1080p) [Batch]"),
+            entry("[SubsPlease] Frieren - 06 (720p).mkv"),
+            entry("[SubsPlease] Sousou no Frieren - 07 (720p).mkv"),
+        ];
+        let names = |entries: Vec<Entry>| -> Vec<String> {
+            entries.into_iter().map(|entry| entry.name).collect()
+        };
+
+        assert_eq!(
+            names(subscription.pending(entries.clone(), &show).unwrap()),
+            [
+                "[SubsPlease] Frieren - 05 (1080p).mkv",
+                "[SubsPlease] Frieren - 06 (720p).mkv",
+                "[SubsPlease] Sousou no Frieren - 07 (720p).mkv"
+            ]
+        );
+
+        // The spin-off isn't the show, even if it's the only episode 7
+        assert_eq!(
+            names(subscription.pending(entries.clone(), &show[..1]).unwrap()),
+            [
+                "[SubsPlease] Frieren - 05 (1080p).mkv",
+                "[SubsPlease] Frieren - 06 (720p).mkv"
+            ]
+        );
+        assert!(!subscription.wants(&entries[0], &show));
+        assert!(subscription.wants(&entries[1], &show));
+
+        subscription.filter = Some("res:1080p".to_owned());
+        assert_eq!(
+            names(subscription.pending(entries.clone(), &show).unwrap()),
+            ["[SubsPlease] Frieren - 05 (1080p).mkv"]
+        );
+
+        subscription.filter = Some("/(unclosed/".to_owned());
+        assert!(subscription.pending(entries.clone(), &show).is_err());
+        assert!(!subscription.wants(&entries[1], &show));
+    }
+}